@@ -0,0 +1,520 @@
+use crate::dt_value::{strings, Cell};
+use crate::root::{path_string, DTNode, Root};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub address: u128,
+    pub size: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub child: u128,
+    pub parent: u128,
+    pub size: u128,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ranges {
+    Identity,
+    Map(Vec<Range>),
+}
+
+#[derive(Debug)]
+pub enum AddressError {
+    BadCellCount(String, usize, usize),
+    Unresolved(String, String),
+    TooWide(String, u32),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::BadCellCount(p, n, e) => write!(
+                f,
+                "{} has {} cells, expected a multiple of {}",
+                p, n, e
+            ),
+            AddressError::Unresolved(p, s) => write!(f, "{} uses unresolved value {}", p, s),
+            AddressError::TooWide(p, n) => write!(f, "{} needs {} cells per number", p, n),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TranslateError {
+    NoRanges(Vec<String>),
+    OutsideRanges(Vec<String>, u128),
+    Bad(Vec<String>, AddressError),
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranslateError::NoRanges(p) => write!(f, "{} has no ranges", path_string(p)),
+            TranslateError::OutsideRanges(p, a) => write!(
+                f,
+                "{:#x} is outside the ranges of {}",
+                a,
+                path_string(p)
+            ),
+            TranslateError::Bad(p, e) => write!(f, "{}: {}", path_string(p), e),
+        }
+    }
+}
+
+pub struct Window {
+    pub path: Vec<String>,
+    pub local: Region,
+    pub cpu: Result<u128, TranslateError>,
+}
+
+pub struct Decoded {
+    pub path: Vec<String>,
+    pub reg: Option<Result<Vec<Region>, AddressError>>,
+    pub ranges: Option<Result<Ranges, AddressError>>,
+    pub dma_ranges: Option<Result<Ranges, AddressError>>,
+}
+
+fn cell_property(root: &Root, node: Option<&DTNode>, name: &str, default: u32) -> u32 {
+    node.and_then(|n| root.cell_value(n, name))
+        .unwrap_or(default)
+}
+
+pub fn address_cells(root: &Root, node: Option<&DTNode>) -> u32 {
+    cell_property(root, node, "#address-cells", 2)
+}
+
+pub fn size_cells(root: &Root, node: Option<&DTNode>) -> u32 {
+    cell_property(root, node, "#size-cells", 1)
+}
+
+fn parent<'a>(root: &'a Root, path: &[String]) -> Option<&'a DTNode> {
+    if path.len() > 1 {
+        root.get_node(&path[..path.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn numbers(root: &Root, node: &DTNode, property: &str) -> Result<Vec<u32>, AddressError> {
+    let raw = match node.property(property) {
+        Some(r) => r,
+        None => return Ok(Vec::new()),
+    };
+    let mut nums = Vec::new();
+    for c in root.cells(raw) {
+        match c {
+            Cell::Num(n) => nums.push(n),
+            Cell::Ref(r) => return Err(AddressError::Unresolved(property.to_string(), r)),
+            Cell::Symbol(s) => return Err(AddressError::Unresolved(property.to_string(), s)),
+        }
+    }
+    Ok(nums)
+}
+
+fn join(cells: &[u32]) -> u128 {
+    cells.iter().fold(0, |a, c| (a << 32) | *c as u128)
+}
+
+fn check_layout(property: &str, cells: &[u32], widths: &[u32]) -> Result<usize, AddressError> {
+    for w in widths {
+        if *w > 4 {
+            return Err(AddressError::TooWide(property.to_string(), *w));
+        }
+    }
+    let entry: usize = widths.iter().map(|w| *w as usize).sum();
+    if entry == 0 || cells.len() % entry != 0 {
+        return Err(AddressError::BadCellCount(
+            property.to_string(),
+            cells.len(),
+            entry,
+        ));
+    }
+    Ok(entry)
+}
+
+pub fn decode_reg(root: &Root, path: &[String]) -> Option<Result<Vec<Region>, AddressError>> {
+    let node = root.get_node(path)?;
+    if !node.has_property("reg") {
+        return None;
+    }
+    let bus = parent(root, path);
+    let ac = address_cells(root, bus);
+    let sc = size_cells(root, bus);
+    let cells = match numbers(root, node, "reg") {
+        Ok(c) => c,
+        Err(e) => return Some(Err(e)),
+    };
+    let entry = match check_layout("reg", &cells, &[ac, sc]) {
+        Ok(e) => e,
+        Err(e) => return Some(Err(e)),
+    };
+    let regions = cells
+        .chunks(entry)
+        .map(|c| Region {
+            address: join(&c[..ac as usize]),
+            size: join(&c[ac as usize..]),
+        })
+        .collect();
+    Some(Ok(regions))
+}
+
+// Each `ranges`/`dma-ranges` entry is (child address, parent address, size),
+// sized by the node's own cells, its parent's #address-cells and its own
+// #size-cells respectively.
+pub fn decode_ranges(
+    root: &Root,
+    path: &[String],
+    property: &str,
+) -> Option<Result<Ranges, AddressError>> {
+    let node = root.get_node(path)?;
+    match node.property(property)? {
+        None => return Some(Ok(Ranges::Identity)),
+        Some(r) if r.trim().is_empty() => return Some(Ok(Ranges::Identity)),
+        _ => (),
+    };
+    let bus = parent(root, path);
+    let child = address_cells(root, Some(node));
+    let up = address_cells(root, bus);
+    let sc = size_cells(root, Some(node));
+    let cells = match numbers(root, node, property) {
+        Ok(c) => c,
+        Err(e) => return Some(Err(e)),
+    };
+    if cells.is_empty() {
+        return Some(Ok(Ranges::Identity));
+    }
+    let entry = match check_layout(property, &cells, &[child, up, sc]) {
+        Ok(e) => e,
+        Err(e) => return Some(Err(e)),
+    };
+    let (c, p) = (child as usize, (child + up) as usize);
+    let ranges = cells
+        .chunks(entry)
+        .map(|e| Range {
+            child: join(&e[..c]),
+            parent: join(&e[c..p]),
+            size: join(&e[p..]),
+        })
+        .collect();
+    Some(Ok(Ranges::Map(ranges)))
+}
+
+pub fn decode(root: &Root) -> Vec<Decoded> {
+    root.nodes()
+        .into_iter()
+        .map(|(path, _)| Decoded {
+            reg: decode_reg(root, &path),
+            ranges: decode_ranges(root, &path, "ranges"),
+            dma_ranges: decode_ranges(root, &path, "dma-ranges"),
+            path,
+        })
+        .collect()
+}
+
+pub fn is_pci(node: &DTNode) -> bool {
+    match node.property("device_type") {
+        Some(t) => strings(t).iter().any(|t| t == "pci" || t == "pciex"),
+        None => false,
+    }
+}
+
+// PCI child addresses are (phys.hi, phys.mid, phys.lo): only the space code in
+// phys.hi and the 64 bit address take part in matching a range, the same as
+// the kernel's of_bus_pci_map.
+fn map_range(pci: bool, address: u128, r: &Range) -> Option<u128> {
+    let (offset, base) = if pci {
+        let space = |a: u128| (a >> 88) & 0x3;
+        if space(address) != space(r.child) {
+            return None;
+        }
+        (address & 0xffff_ffff_ffff_ffff, r.child & 0xffff_ffff_ffff_ffff)
+    } else {
+        (address, r.child)
+    };
+    if offset >= base && offset - base < r.size {
+        Some(r.parent + (offset - base))
+    } else {
+        None
+    }
+}
+
+pub fn translate(root: &Root, path: &[String], address: u128) -> Result<u128, TranslateError> {
+    let mut address = address;
+    let mut depth = path.len() - 1;
+    while depth > 1 {
+        let bus_path = &path[..depth];
+        let bus = match root.get_node(bus_path) {
+            Some(b) => b,
+            None => return Err(TranslateError::NoRanges(bus_path.to_vec())),
+        };
+        match decode_ranges(root, bus_path, "ranges") {
+            None => return Err(TranslateError::NoRanges(bus_path.to_vec())),
+            Some(Err(e)) => return Err(TranslateError::Bad(bus_path.to_vec(), e)),
+            Some(Ok(Ranges::Identity)) => (),
+            Some(Ok(Ranges::Map(m))) => {
+                let pci = is_pci(bus);
+                match m.iter().find_map(|r| map_range(pci, address, r)) {
+                    Some(a) => address = a,
+                    None => return Err(TranslateError::OutsideRanges(bus_path.to_vec(), address)),
+                }
+            }
+        }
+        depth -= 1;
+    }
+    Ok(address)
+}
+
+pub fn windows(root: &Root) -> Vec<Window> {
+    let mut found = Vec::new();
+    for d in decode(root) {
+        match d.reg {
+            Some(Ok(regs)) => {
+                for r in regs {
+                    found.push(Window {
+                        cpu: translate(root, &d.path, r.address),
+                        path: d.path.clone(),
+                        local: r,
+                    });
+                }
+            }
+            ref e => warn(&path_string(&d.path), e),
+        }
+    }
+    found
+}
+
+fn print_ranges(name: &str, ranges: &Option<Result<Ranges, AddressError>>) {
+    match ranges {
+        Some(Ok(Ranges::Identity)) => println!("    {}: identity", name),
+        Some(Ok(Ranges::Map(m))) => {
+            for r in m {
+                println!(
+                    "    {}: {:#x} -> {:#x} size {:#x}",
+                    name, r.child, r.parent, r.size
+                );
+            }
+        }
+        Some(Err(_)) | None => (),
+    }
+}
+
+fn warn<T>(path: &str, decoded: &Option<Result<T, AddressError>>) {
+    if let Some(Err(e)) = decoded {
+        eprintln!("warning: {}: {}", path, e);
+    }
+}
+
+pub fn print_reg(root: &Root) {
+    for d in decode(root) {
+        let path = path_string(&d.path);
+        warn(&path, &d.reg);
+        warn(&path, &d.ranges);
+        warn(&path, &d.dma_ranges);
+        let has_reg = match &d.reg {
+            Some(Ok(r)) => !r.is_empty(),
+            _ => false,
+        };
+        let has_ranges = match (&d.ranges, &d.dma_ranges) {
+            (Some(Ok(_)), _) | (_, Some(Ok(_))) => true,
+            _ => false,
+        };
+        if !has_reg && !has_ranges {
+            continue;
+        }
+        println!("{}", path);
+        if let Some(Ok(regs)) = &d.reg {
+            for r in regs {
+                println!("    reg: {:#x} size {:#x}", r.address, r.size);
+            }
+        }
+        print_ranges("ranges", &d.ranges);
+        print_ranges("dma-ranges", &d.dma_ranges);
+    }
+}
+
+pub fn print_translation(root: &Root) {
+    let mut last: Option<Vec<String>> = None;
+    for w in windows(root) {
+        if last.as_ref() != Some(&w.path) {
+            println!("{}", path_string(&w.path));
+        }
+        match &w.cpu {
+            Ok(a) => println!(
+                "    {:#x} -> {:#x} size {:#x}",
+                w.local.address, a, w.local.size
+            ),
+            Err(e) => println!(
+                "    {:#x} size {:#x}: cannot translate, {}",
+                w.local.address, w.local.size, e
+            ),
+        }
+        last = Some(w.path);
+    }
+}
+
+pub fn print_memory_map(root: &Root) {
+    let mut map: Vec<(Region, String)> = Vec::new();
+    for w in windows(root).into_iter().filter(|w| w.local.size > 0) {
+        match w.cpu {
+            Ok(a) => map.push((
+                Region {
+                    address: a,
+                    size: w.local.size,
+                },
+                path_string(&w.path),
+            )),
+            Err(e) => eprintln!(
+                "warning: {}: {:#x} not in the memory map, {}",
+                path_string(&w.path),
+                w.local.address,
+                e
+            ),
+        }
+    }
+    map.sort_by(|a, b| (a.0.address, a.0.size).cmp(&(b.0.address, b.0.size)));
+    let wide = map.iter().any(|(r, _)| r.address.saturating_add(r.size) > 1 << 32);
+    for (r, path) in map {
+        let end = r.address.saturating_add(r.size.saturating_sub(1));
+        if wide {
+            println!("{:#018x}-{:#018x}  {:>#12x}  {}", r.address, end, r.size, path);
+        } else {
+            println!("{:#010x}-{:#010x}  {:>#12x}  {}", r.address, end, r.size, path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_reg, translate, AddressError, Region, TranslateError};
+    use crate::root::Root;
+    use crate::{load_str, Options};
+
+    fn tree(body: &str) -> Root {
+        let dts = format!("/dts-v1/;\n/ {{\n{}}};\n", body);
+        load_str("a.dts", &dts, &Options::default()).unwrap()
+    }
+
+    fn path(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn default_cells() {
+        // Without #address-cells/#size-cells a bus has 2 and 1.
+        let r = tree("\tdev@0 { reg = <0x1 0x2000 0x100>; };\n");
+        let regions = decode_reg(&r, &path(&["/", "dev@0"])).unwrap().unwrap();
+        assert_eq!(
+            regions,
+            vec![Region {
+                address: 0x1_0000_2000,
+                size: 0x100
+            }]
+        );
+    }
+
+    #[test]
+    fn wide_addresses_and_sizes() {
+        let r = tree(
+            "\t#address-cells = <2>;\n\t#size-cells = <2>;\n\
+             \tmemory@80000000 { reg = <0x0 0x80000000 0x1 0x0>, <0x8 0x0 0x0 0x1000>; };\n",
+        );
+        let regions = decode_reg(&r, &path(&["/", "memory@80000000"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            regions,
+            vec![
+                Region {
+                    address: 0x8000_0000,
+                    size: 0x1_0000_0000
+                },
+                Region {
+                    address: 0x8_0000_0000,
+                    size: 0x1000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_size_cells() {
+        let r = tree("\t#address-cells = <1>;\n\t#size-cells = <0>;\n\tcpu@1 { reg = <1>; };\n");
+        let regions = decode_reg(&r, &path(&["/", "cpu@1"])).unwrap().unwrap();
+        assert_eq!(
+            regions,
+            vec![Region {
+                address: 1,
+                size: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_layouts() {
+        let r = tree(
+            "\t#address-cells = <1>;\n\t#size-cells = <1>;\n\
+             \ta { reg = <1 2 3>; };\n\
+             \tbus { #address-cells = <5>; b { reg = <1 2 3 4 5 6>; }; };\n",
+        );
+        match decode_reg(&r, &path(&["/", "a"])) {
+            Some(Err(AddressError::BadCellCount(_, 3, 2))) => (),
+            _ => panic!("expected a bad cell count"),
+        }
+        match decode_reg(&r, &path(&["/", "bus", "b"])) {
+            Some(Err(AddressError::TooWide(_, 5))) => (),
+            _ => panic!("expected too wide"),
+        }
+        assert!(decode_reg(&r, &path(&["/", "bus"])).is_none());
+    }
+
+    const BUSES: &str = "\t#address-cells = <1>;\n\t#size-cells = <1>;\n\
+        \tsoc {\n\t\tcompatible = \"simple-bus\";\n\t\t#address-cells = <1>;\n\t\t#size-cells = <1>;\n\
+        \t\tranges = <0x0 0x40000000 0x10000000>;\n\
+        \t\tbus@1000 {\n\t\t\t#address-cells = <1>;\n\t\t\t#size-cells = <1>;\n\
+        \t\t\tranges = <0x0 0x1000 0x1000>;\n\t\t\tdev@10 { reg = <0x10 0x4>; };\n\t\t};\n\
+        \t\tflat { #address-cells = <1>; #size-cells = <1>; ranges; dev@20 { reg = <0x20 0x4>; }; };\n\
+        \t\tclosed { #address-cells = <1>; #size-cells = <1>; dev@30 { reg = <0x30 0x4>; }; };\n\
+        \t};\n";
+
+    #[test]
+    fn translates_through_two_levels() {
+        let r = tree(BUSES);
+        let dev = path(&["/", "soc", "bus@1000", "dev@10"]);
+        assert_eq!(translate(&r, &dev, 0x10).unwrap(), 0x4000_1010);
+        match translate(&r, &dev, 0x2000) {
+            Err(TranslateError::OutsideRanges(p, 0x2000)) => assert_eq!(p, dev[..3].to_vec()),
+            _ => panic!("expected an address outside the ranges"),
+        }
+    }
+
+    #[test]
+    fn empty_and_missing_ranges() {
+        let r = tree(BUSES);
+        let flat = path(&["/", "soc", "flat", "dev@20"]);
+        assert_eq!(translate(&r, &flat, 0x20).unwrap(), 0x4000_0020);
+        let closed = path(&["/", "soc", "closed", "dev@30"]);
+        match translate(&r, &closed, 0x30) {
+            Err(TranslateError::NoRanges(p)) => assert_eq!(p, closed[..3].to_vec()),
+            _ => panic!("expected no ranges"),
+        }
+    }
+
+    #[test]
+    fn pci_matches_space_code_only() {
+        let r = tree(
+            "\t#address-cells = <1>;\n\t#size-cells = <1>;\n\
+             \tpci {\n\t\tdevice_type = \"pci\";\n\t\t#address-cells = <3>;\n\t\t#size-cells = <2>;\n\
+             \t\tranges = <0x02000000 0x0 0x1000 0x50000000 0x0 0x10000>;\n\
+             \t\tdev@1 { reg = <0x02000800 0x0 0x1100 0x0 0x10>; };\n\t};\n",
+        );
+        let dev = path(&["/", "pci", "dev@1"]);
+        let reg = decode_reg(&r, &dev).unwrap().unwrap();
+        assert_eq!(translate(&r, &dev, reg[0].address).unwrap(), 0x5000_0100);
+        // I/O space is not in the memory window.
+        let io = 0x0100_0000u128 << 64 | 0x1100;
+        assert!(translate(&r, &dev, io).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+use crate::dt_address::{decode_ranges, decode_reg, is_pci, AddressError};
+use crate::dt_value::{parse, strings, Cell, Value};
+use crate::root::{location, path_string, DTNode, Location, Root};
+use std::fmt;
+use std::str;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Off,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Off => write!(f, "off"),
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+// The checks and their default level, named after their dtc counterparts
+// where dtc has one.
+const CHECKS: &[(&str, Level)] = &[
+    ("duplicate_node_names", Level::Error),
+    ("duplicate_property_names", Level::Error),
+    ("duplicate_label", Level::Error),
+    ("node_name_chars", Level::Error),
+    ("property_name_chars", Level::Error),
+    ("phandle_references", Level::Error),
+    ("unit_address_vs_reg", Level::Warning),
+    ("unit_address_format", Level::Warning),
+    ("unit_address_mismatch", Level::Warning),
+    ("reg_format", Level::Warning),
+    ("ranges_format", Level::Warning),
+    ("addr_size_cells", Level::Warning),
+    ("avoid_unnecessary_addr_size", Level::Warning),
+    ("compatible_is_string_list", Level::Warning),
+    ("names_is_string_list", Level::Warning),
+    ("device_type_is_string", Level::Warning),
+    ("model_is_string", Level::Warning),
+    ("status_is_string", Level::Warning),
+    ("device_type", Level::Warning),
+];
+
+// A `-W`/`-E` argument: a check name, or `no-` and a check name.
+#[derive(Debug)]
+pub struct Toggle {
+    pub check: String,
+    pub on: bool,
+}
+
+impl str::FromStr for Toggle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (check, on) = match s.strip_prefix("no-") {
+            Some(c) => (c, false),
+            None => (s, true),
+        };
+        let check = check.replace('-', "_");
+        if CHECKS.iter().any(|(c, _)| *c == check) {
+            Ok(Toggle { check, on })
+        } else {
+            Err(format!("Unknown check {}", s))
+        }
+    }
+}
+
+// Like dtc, a check has a warning and an error flag; `-W` flips the first and
+// `-E` the second, and the error flag wins.
+pub struct Config {
+    flags: Vec<(&'static str, bool, bool)>,
+}
+
+impl Config {
+    pub fn new(warnings: &[Toggle], errors: &[Toggle]) -> Self {
+        let mut flags: Vec<(&'static str, bool, bool)> = CHECKS
+            .iter()
+            .map(|(c, l)| (*c, *l == Level::Warning, *l == Level::Error))
+            .collect();
+        for t in warnings {
+            if let Some(f) = flags.iter_mut().find(|f| f.0 == t.check) {
+                f.1 = t.on;
+            }
+        }
+        for t in errors {
+            if let Some(f) = flags.iter_mut().find(|f| f.0 == t.check) {
+                f.2 = t.on;
+            }
+        }
+        Config { flags }
+    }
+
+    pub fn level(&self, check: &str) -> Level {
+        match self.flags.iter().find(|f| f.0 == check) {
+            Some((_, _, true)) => Level::Error,
+            Some((_, true, _)) => Level::Warning,
+            _ => Level::Off,
+        }
+    }
+}
+
+pub struct Finding {
+    pub check: &'static str,
+    pub level: Level,
+    pub path: Vec<String>,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+struct Lint<'a> {
+    root: &'a Root,
+    config: &'a Config,
+    out: Vec<Finding>,
+}
+
+fn is_string_list(raw: &Option<String>) -> bool {
+    match raw {
+        Some(r) => {
+            let values = parse(r);
+            !values.is_empty() && values.iter().all(|v| matches!(v, Value::Str(_)))
+        }
+        None => false,
+    }
+}
+
+fn unit_address(node: &DTNode) -> Option<&str> {
+    node.name.splitn(2, '@').nth(1)
+}
+
+impl<'a> Lint<'a> {
+    fn report(
+        &mut self,
+        check: &'static str,
+        path: &[String],
+        property: Option<&str>,
+        message: String,
+    ) {
+        self.report_at(check, path, location(self.root, path, property), message);
+    }
+
+    fn report_at(
+        &mut self,
+        check: &'static str,
+        path: &[String],
+        location: Option<Location>,
+        message: String,
+    ) {
+        let level = self.config.level(check);
+        if level != Level::Off {
+            self.out.push(Finding {
+                check,
+                level,
+                path: path.to_vec(),
+                location,
+                message,
+            });
+        }
+    }
+
+    fn names(&mut self, path: &[String], node: &DTNode) {
+        let node_chars = |c: char| c.is_ascii_alphanumeric() || ",._+@-".contains(c);
+        if path.len() > 1
+            && (!node.name.chars().all(node_chars) || node.name.matches('@').count() > 1)
+        {
+            self.report(
+                "node_name_chars",
+                path,
+                None,
+                format!("bad character in node name {}", node.name),
+            );
+        }
+        let property_chars = |c: char| c.is_ascii_alphanumeric() || ",._+?#-".contains(c);
+        for (name, _) in node.properties() {
+            if !name.chars().all(property_chars) {
+                self.report(
+                    "property_name_chars",
+                    path,
+                    Some(name),
+                    format!("bad character in property name {}", name),
+                );
+            }
+        }
+    }
+
+    fn unit_address(&mut self, path: &[String], node: &DTNode) {
+        let has_reg = node.has_property("reg");
+        let unit = match unit_address(node) {
+            Some(u) => u,
+            None => {
+                if has_reg && path.len() > 1 {
+                    self.report(
+                        "unit_address_vs_reg",
+                        path,
+                        Some("reg"),
+                        "node has a reg property, but no unit name".to_string(),
+                    );
+                }
+                return;
+            }
+        };
+        if !has_reg && !node.has_property("ranges") {
+            self.report(
+                "unit_address_vs_reg",
+                path,
+                None,
+                "node has a unit name, but no reg or ranges property".to_string(),
+            );
+        }
+        if unit.starts_with("0x") || unit.starts_with("0X") {
+            self.report(
+                "unit_address_format",
+                path,
+                None,
+                "unit name should not have leading \"0x\"".to_string(),
+            );
+        } else if unit.len() > 1 && unit.starts_with('0') && !unit[1..].starts_with(',') {
+            self.report(
+                "unit_address_format",
+                path,
+                None,
+                "unit name should not have leading 0s".to_string(),
+            );
+        }
+
+        // PCI unit addresses are device,function rather than an address.
+        let pci = self
+            .root
+            .get_node(&path[..path.len() - 1])
+            .map_or(false, is_pci);
+        let first = unit
+            .split(',')
+            .next()
+            .unwrap_or("")
+            .trim_start_matches("0x");
+        if let (false, Some(Ok(regions)), Ok(a)) = (
+            pci,
+            decode_reg(self.root, path),
+            u128::from_str_radix(first, 16),
+        ) {
+            if let Some(r) = regions.first().filter(|r| r.address != a) {
+                self.report(
+                    "unit_address_mismatch",
+                    path,
+                    Some("reg"),
+                    format!(
+                        "unit address {} does not match reg address {:#x}",
+                        unit, r.address
+                    ),
+                );
+            }
+        }
+    }
+
+    fn layout(&mut self, path: &[String], node: &DTNode) {
+        if let Some(Err(e @ AddressError::BadCellCount(..))) = decode_reg(self.root, path) {
+            self.report("reg_format", path, Some("reg"), e.to_string());
+        }
+        if let Some(Err(e @ AddressError::BadCellCount(..))) =
+            decode_ranges(self.root, path, "ranges")
+        {
+            self.report("ranges_format", path, Some("ranges"), e.to_string());
+        }
+
+        let child_reg = node.children().iter().any(|c| c.has_property("reg"));
+        for cells in ["#address-cells", "#size-cells"].iter() {
+            if child_reg && !node.has_property(cells) {
+                self.report(
+                    "addr_size_cells",
+                    path,
+                    None,
+                    format!(
+                        "children have reg but {} is missing, relying on default",
+                        cells
+                    ),
+                );
+            }
+        }
+        let sized = node.has_property("#address-cells") || node.has_property("#size-cells");
+        // An interrupt controller's #address-cells sizes interrupt-map entries.
+        if sized
+            && !child_reg
+            && !node.has_property("ranges")
+            && !node.has_property("interrupt-controller")
+        {
+            self.report(
+                "avoid_unnecessary_addr_size",
+                path,
+                None,
+                "unnecessary #address-cells/#size-cells without ranges or child reg".to_string(),
+            );
+        }
+    }
+
+    fn types(&mut self, path: &[String], node: &DTNode) {
+        for (name, raw) in node.properties() {
+            if name == "compatible" && !is_string_list(raw) {
+                self.report(
+                    "compatible_is_string_list",
+                    path,
+                    Some(name),
+                    "compatible is not a string list".to_string(),
+                );
+            }
+            if name.ends_with("-names") && !is_string_list(raw) {
+                self.report(
+                    "names_is_string_list",
+                    path,
+                    Some(name),
+                    format!("{} is not a string list", name),
+                );
+            }
+            let single = match name.as_str() {
+                "device_type" => Some("device_type_is_string"),
+                "model" => Some("model_is_string"),
+                "status" => Some("status_is_string"),
+                _ => None,
+            };
+            if let Some(check) = single {
+                if !is_string_list(raw) || strings(raw).len() != 1 {
+                    self.report(check, path, Some(name), format!("{} is not a string", name));
+                }
+            }
+        }
+
+        let device_type = node
+            .property("device_type")
+            .map(strings)
+            .unwrap_or_default();
+        match device_type.first().map(|t| t.as_str()) {
+            Some("cpu") | Some("memory") | Some("pci") | Some("pciex") => (),
+            Some(t) => self.report(
+                "device_type",
+                path,
+                Some("device_type"),
+                format!(
+                    "device_type \"{}\" is deprecated outside cpu, memory and pci",
+                    t
+                ),
+            ),
+            None => {
+                if path.len() == 2 && node.name.split('@').next() == Some("memory") {
+                    self.report(
+                        "device_type",
+                        path,
+                        None,
+                        "memory node without device_type = \"memory\"".to_string(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn references(&mut self, path: &[String], node: &DTNode) {
+        for (name, raw) in node.properties() {
+            let raw = match raw {
+                Some(r) => r,
+                None => continue,
+            };
+            for v in parse(raw) {
+                let labels = match v {
+                    Value::Cells(c) => c
+                        .into_iter()
+                        .filter_map(|c| match c {
+                            Cell::Ref(l) => Some(l),
+                            _ => None,
+                        })
+                        .collect(),
+                    Value::Ref(l) => vec![l],
+                    Value::Str(_) => Vec::new(),
+                };
+                for l in labels {
+                    if self.root.resolve_phandle(&Cell::Ref(l.clone())).is_none() {
+                        self.report(
+                            "phandle_references",
+                            path,
+                            Some(name),
+                            format!("reference to non-existent node or label \"{}\"", l),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn run(root: &Root, config: &Config) -> Vec<Finding> {
+    let mut lint = Lint {
+        root,
+        config,
+        out: Vec::new(),
+    };
+    for (path, node) in root.nodes() {
+        lint.names(&path, node);
+        lint.unit_address(&path, node);
+        lint.layout(&path, node);
+        lint.types(&path, node);
+        lint.references(&path, node);
+    }
+    for (label, first, second) in root.duplicate_labels() {
+        lint.report(
+            "duplicate_label",
+            second,
+            None,
+            format!("label {} also defined on {}", label, path_string(first)),
+        );
+    }
+    for (path, property, loc) in root.duplicates() {
+        match property {
+            Some(p) => lint.report_at(
+                "duplicate_property_names",
+                path,
+                Some(loc.clone()),
+                format!("duplicate property {}", p),
+            ),
+            None => lint.report_at(
+                "duplicate_node_names",
+                path,
+                Some(loc.clone()),
+                "duplicate node name".to_string(),
+            ),
+        }
+    }
+    lint.out
+}
+
+pub fn print_findings(findings: &[Finding]) {
+    for f in findings {
+        let at = match &f.location {
+            Some(l) => format!("{}: ", l),
+            None => String::new(),
+        };
+        println!(
+            "{}{}: {}: {} [{}]",
+            at,
+            f.level,
+            path_string(&f.path),
+            f.message,
+            f.check
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Config, Level, Toggle, CHECKS};
+    use crate::{load_str, Options};
+
+    // Trips every check at least once.
+    const DTS: &str = "/dts-v1/;
+/ {
+	#address-cells = <1>;
+	#size-cells = <1>;
+	model = <1>;
+	compatible = <2>;
+	status = \"okay\", \"ok\";
+	bad@prop;
+	two@1@2 { reg = <1 4>; };
+	hex@0x10 { reg = <0x10 4>; };
+	off@20 { reg = <0x30 4>; };
+	noreg@40 { };
+	regonly { reg = <0x50 4>; };
+	short@60 { reg = <0x60>; };
+	bus { #address-cells = <1>; #size-cells = <1>; ranges = <1 2>; };
+	empty { #address-cells = <1>; #size-cells = <0>; };
+	nocells { child@1 { reg = <1>; }; };
+	serial { clock-names = <1>; device_type = \"serial\"; clocks = <&missing>; };
+	cpu { device_type = \"cpu\", \"x\"; };
+	memory@0 { reg = <0 4>; };
+	l: one { };
+	l: two { };
+	dup { p; p; };
+	dup { };
+};
+";
+
+    fn levels(warnings: &[&str], errors: &[&str]) -> Vec<(&'static str, Level)> {
+        let parse = |t: &[&str]| -> Vec<Toggle> { t.iter().map(|s| s.parse().unwrap()).collect() };
+        let config = Config::new(&parse(warnings), &parse(errors));
+        let root = load_str("a.dts", DTS, &Options::default()).unwrap();
+        let mut found: Vec<(&str, Level)> = run(&root, &config)
+            .into_iter()
+            .map(|f| (f.check, f.level))
+            .collect();
+        found.sort_by_key(|(c, _)| *c);
+        found.dedup();
+        found
+    }
+
+    fn defaults() -> Vec<(&'static str, Level)> {
+        let mut all = CHECKS.to_vec();
+        all.sort_by_key(|(c, _)| *c);
+        all
+    }
+
+    #[test]
+    fn every_check_fires_by_default() {
+        assert_eq!(levels(&[], &[]), defaults());
+    }
+
+    #[test]
+    fn each_check_toggles() {
+        for (check, level) in CHECKS {
+            let name = check.replace('_', "-");
+            let no = format!("no-{}", name);
+            let with = |l: Level| -> Vec<(&str, Level)> {
+                defaults()
+                    .into_iter()
+                    .map(|(c, d)| (c, if c == *check { l } else { d }))
+                    .filter(|(_, l)| *l != Level::Off)
+                    .collect()
+            };
+            assert_eq!(levels(&[], &[&name]), with(Level::Error), "-E {}", name);
+            assert_eq!(levels(&[&no], &[&no]), with(Level::Off), "-W/-E {}", no);
+            assert_eq!(
+                levels(&[&name], &[&no]),
+                with(Level::Warning),
+                "-W {}",
+                name
+            );
+            let off = if *level == Level::Error {
+                levels(&[], &[&no])
+            } else {
+                levels(&[&no], &[])
+            };
+            assert_eq!(off, with(Level::Off), "{}", no);
+        }
+    }
+
+    #[test]
+    fn unknown_check() {
+        assert!("no-such-check".parse::<Toggle>().is_err());
+        let t: Toggle = "no-reg-format".parse().unwrap();
+        assert_eq!((t.check.as_str(), t.on), ("reg_format", false));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::dt_dot::Graph;
+use crate::dt_phandle::{specifiers, Spec, SpecError};
+use crate::dt_value::{strings, Cell};
+use crate::root::{path_string, Root};
+
+pub struct ClockUse {
+    pub consumer: Vec<String>,
+    pub name: Option<String>,
+    pub clock: Spec,
+}
+
+pub struct Assignment {
+    pub node: Vec<String>,
+    pub clock: Spec,
+    pub parent: Option<Spec>,
+    pub rate: Option<u32>,
+}
+
+pub struct ClockGraph {
+    pub providers: Vec<Vec<String>>,
+    pub uses: Vec<ClockUse>,
+    pub assignments: Vec<Assignment>,
+    pub warnings: Vec<String>,
+}
+
+fn collect(
+    root: &Root,
+    path: &[String],
+    property: &str,
+    warnings: &mut Vec<String>,
+) -> Vec<Option<Spec>> {
+    let raw = match root.get_node(path).and_then(|n| n.property(property)) {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    for s in specifiers(root, raw, "#clock-cells") {
+        match s {
+            Ok(s) => found.push(s),
+            Err(SpecError::NoCells(p, _)) => warnings.push(format!(
+                "{}: {} references {} which is not a clock provider",
+                path_string(path),
+                property,
+                path_string(&p)
+            )),
+            Err(e) => warnings.push(format!("{}: {}: {}", path_string(path), property, e)),
+        }
+    }
+    found
+}
+
+pub fn graph(root: &Root) -> ClockGraph {
+    let mut g = ClockGraph {
+        providers: Vec::new(),
+        uses: Vec::new(),
+        assignments: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for (path, node) in root.nodes() {
+        if node.has_property("#clock-cells") {
+            g.providers.push(path.clone());
+        }
+        let clocks = collect(root, &path, "clocks", &mut g.warnings);
+        let names = node
+            .property("clock-names")
+            .map(strings)
+            .unwrap_or_default();
+        if node.has_property("clock-names") && names.len() != clocks.len() {
+            g.warnings.push(format!(
+                "{}: {} clocks but {} clock-names",
+                path_string(&path),
+                clocks.len(),
+                names.len()
+            ));
+        }
+        for (i, c) in clocks.into_iter().enumerate() {
+            if let Some(clock) = c {
+                g.uses.push(ClockUse {
+                    consumer: path.clone(),
+                    name: names.get(i).cloned(),
+                    clock,
+                });
+            }
+        }
+
+        let assigned = collect(root, &path, "assigned-clocks", &mut g.warnings);
+        let parents = collect(root, &path, "assigned-clock-parents", &mut g.warnings);
+        let rates = root.cells(node.property("assigned-clock-rates").unwrap_or(&None));
+        if parents.len() > assigned.len() || rates.len() > assigned.len() {
+            g.warnings.push(format!(
+                "{}: more assigned-clock-parents/rates than assigned-clocks",
+                path_string(&path)
+            ));
+        }
+        for (i, c) in assigned.into_iter().enumerate() {
+            if let Some(clock) = c {
+                g.assignments.push(Assignment {
+                    node: path.clone(),
+                    clock,
+                    parent: parents.get(i).cloned().unwrap_or(None),
+                    rate: match rates.get(i) {
+                        Some(Cell::Num(r)) if *r != 0 => Some(*r),
+                        _ => None,
+                    },
+                });
+            }
+        }
+    }
+    g
+}
+
+pub fn clock_name(root: &Root, clock: &Spec) -> String {
+    let args: Vec<String> = clock.args.iter().map(|a| a.to_string()).collect();
+    let index = if clock.args.len() == 1 {
+        clock.args[0] as usize
+    } else {
+        0
+    };
+    let output = root
+        .get_node(&clock.target)
+        .and_then(|n| n.property("clock-output-names"))
+        .map(strings)
+        .and_then(|o| {
+            if clock.args.len() <= 1 {
+                o.get(index).cloned()
+            } else {
+                None
+            }
+        });
+    let mut s = format!("{} <{}>", path_string(&clock.target), args.join(" "));
+    if let Some(o) = output {
+        s.push_str(&format!(" ({})", o));
+    }
+    s
+}
+
+fn consumers(
+    g: &ClockGraph,
+    provider: &[String],
+    depth: usize,
+    seen: &mut Vec<Vec<String>>,
+    reached: &mut Vec<Vec<String>>,
+    out: &mut Vec<String>,
+) {
+    for u in g
+        .uses
+        .iter()
+        .filter(|u| u.clock.target.as_slice() == provider)
+    {
+        let args: Vec<String> = u.clock.args.iter().map(|a| a.to_string()).collect();
+        let name = match &u.name {
+            Some(n) => format!(" as \"{}\"", n),
+            None => String::new(),
+        };
+        out.push(format!(
+            "{}{} <{}>{}",
+            "    ".repeat(depth),
+            path_string(&u.consumer),
+            args.join(" "),
+            name
+        ));
+        if g.providers.contains(&u.consumer) && !seen.contains(&u.consumer) {
+            reached.push(u.consumer.clone());
+            seen.push(u.consumer.clone());
+            consumers(g, &u.consumer, depth + 1, seen, reached, out);
+            seen.pop();
+        }
+    }
+}
+
+// Each provider no other provider feeds, with what it feeds below it. Those
+// only reachable through a loop of providers feeding each other are listed
+// after them, starting from the first not yet shown.
+pub fn tree(g: &ClockGraph) -> Vec<String> {
+    let fed = |p: &Vec<String>| {
+        g.uses
+            .iter()
+            .any(|u| u.consumer == *p && g.providers.contains(&u.clock.target))
+    };
+    let mut out = Vec::new();
+    let mut reached: Vec<Vec<String>> = Vec::new();
+    let roots = g.providers.iter().filter(|p| !fed(p));
+    for p in roots {
+        out.push(path_string(p));
+        reached.push(p.clone());
+        consumers(g, p, 1, &mut vec![p.clone()], &mut reached, &mut out);
+    }
+    for p in &g.providers {
+        if reached.contains(p) {
+            continue;
+        }
+        out.push(format!("{} (clock loop)", path_string(p)));
+        reached.push(p.clone());
+        consumers(g, p, 1, &mut vec![p.clone()], &mut reached, &mut out);
+    }
+    out
+}
+
+pub fn print_tree(root: &Root) {
+    let g = graph(root);
+    for w in &g.warnings {
+        eprintln!("warning: {}", w);
+    }
+    for l in tree(&g) {
+        println!("{}", l);
+    }
+    if !g.assignments.is_empty() {
+        println!("assigned clocks:");
+    }
+    for a in &g.assignments {
+        let mut line = format!(
+            "    {}: {}",
+            path_string(&a.node),
+            clock_name(root, &a.clock)
+        );
+        if let Some(p) = &a.parent {
+            line.push_str(&format!(" parent {}", clock_name(root, p)));
+        }
+        if let Some(r) = a.rate {
+            line.push_str(&format!(" rate {}", r));
+        }
+        println!("{}", line);
+    }
+}
+
+pub fn dot(root: &Root) -> Graph {
+    let g = graph(root);
+    for w in &g.warnings {
+        eprintln!("warning: {}", w);
+    }
+    let mut d = Graph::new("clocks");
+    for p in &g.providers {
+        d.node(path_string(p), path_string(p));
+    }
+    for u in &g.uses {
+        let args: Vec<String> = u.clock.args.iter().map(|a| a.to_string()).collect();
+        let mut label = format!("<{}>", args.join(" "));
+        if let Some(n) = &u.name {
+            label.push_str(&format!(" {}", n));
+        }
+        d.node(path_string(&u.consumer), path_string(&u.consumer));
+        d.edge(
+            path_string(&u.clock.target),
+            path_string(&u.consumer),
+            Some(label),
+        );
+    }
+    for a in &g.assignments {
+        if let Some(p) = &a.parent {
+            d.dashed_edge(
+                path_string(&p.target),
+                path_string(&a.clock.target),
+                Some(format!("assigned to {}", clock_name(root, &a.clock))),
+            );
+        }
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{graph, tree};
+    use crate::{load_str, Options};
+
+    fn lines(body: &str) -> Vec<String> {
+        let dts = format!("/dts-v1/;\n/ {{\n{}}};\n", body);
+        let root = load_str("a.dts", &dts, &Options::default()).unwrap();
+        tree(&graph(&root))
+    }
+
+    #[test]
+    fn providers_and_consumers() {
+        let out = lines(
+            "\tosc: osc { #clock-cells = <0>; };\n\
+             \tpll: pll { #clock-cells = <1>; clocks = <&osc>; };\n\
+             \tuart { clocks = <&pll 2>; clock-names = \"baud\"; };\n",
+        );
+        assert_eq!(
+            out,
+            vec!["/osc", "    /pll <>", "        /uart <2> as \"baud\""]
+        );
+    }
+
+    #[test]
+    fn loop_of_providers_is_shown() {
+        let out = lines(
+            "\ta: a { #clock-cells = <0>; clocks = <&b>; };\n\
+             \tb: b { #clock-cells = <0>; clocks = <&a>; };\n",
+        );
+        assert_eq!(out, vec!["/a (clock loop)", "    /b <>", "        /a <>"]);
+    }
+}
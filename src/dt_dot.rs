@@ -0,0 +1,77 @@
+use crate::root::{path_string, Root};
+
+pub struct Graph {
+    name: String,
+    nodes: Vec<(String, String)>,
+    edges: Vec<(String, String, Option<String>, bool)>,
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Graph {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Graph {
+            name: name.into(),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    pub fn node<S: Into<String>>(&mut self, id: S, label: S) {
+        let id = id.into();
+        if !self.nodes.iter().any(|(i, _)| *i == id) {
+            self.nodes.push((id, label.into()));
+        }
+    }
+
+    pub fn edge<S: Into<String>>(&mut self, from: S, to: S, label: Option<String>) {
+        self.edges.push((from.into(), to.into(), label, false));
+    }
+
+    pub fn dashed_edge<S: Into<String>>(&mut self, from: S, to: S, label: Option<String>) {
+        self.edges.push((from.into(), to.into(), label, true));
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = format!("digraph {} {{\n", quote(&self.name));
+        out.push_str("    node [shape=box];\n");
+        for (id, label) in &self.nodes {
+            out.push_str(&format!("    {} [label={}];\n", quote(id), quote(label)));
+        }
+        for (from, to, label, dashed) in &self.edges {
+            let mut attrs = Vec::new();
+            if let Some(l) = label {
+                attrs.push(format!("label={}", quote(l)));
+            }
+            if *dashed {
+                attrs.push("style=dashed".to_string());
+            }
+            if attrs.is_empty() {
+                out.push_str(&format!("    {} -> {};\n", quote(from), quote(to)));
+            } else {
+                out.push_str(&format!(
+                    "    {} -> {} [{}];\n",
+                    quote(from),
+                    quote(to),
+                    attrs.join(", ")
+                ));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+pub fn tree(root: &Root) -> Graph {
+    let mut g = Graph::new("devicetree");
+    for (path, node) in root.nodes() {
+        let id = path_string(&path);
+        g.node(id.clone(), node.name.clone());
+        if path.len() > 1 {
+            g.edge(path_string(&path[..path.len() - 1]), id, None);
+        }
+    }
+    g
+}
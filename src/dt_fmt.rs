@@ -0,0 +1,298 @@
+use crate::dt_lexer::{lex, DTError, DTInfo, Span};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Gap {
+    None,
+    Space,
+    Newline,
+}
+
+struct Printer<'a> {
+    text: &'a str,
+    out: Vec<String>,
+    depth: usize,
+}
+
+fn collapse(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace(" ;", ";")
+}
+
+// Re-spaces a property value: one space between words, none inside `<>`/`[]`
+// or before `,`, and line breaks kept where the source had them, continued
+// at `indent`. Strings and comments are copied as they are.
+fn format_value(raw: &str, indent: &str) -> String {
+    let mut out = String::new();
+    let mut gap = Gap::None;
+    let mut after_line_comment = false;
+    let chars: Vec<char> = raw.chars().collect();
+    let mut i = 0;
+    let emit = |out: &mut String, gap: Gap| match gap {
+        Gap::None => (),
+        Gap::Space => out.push(' '),
+        Gap::Newline => {
+            out.push('\n');
+            out.push_str(indent);
+        }
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            if c == '\n' && gap == Gap::Space {
+                gap = Gap::Newline;
+            }
+            i += 1;
+            continue;
+        }
+        let start = i;
+        match (c, next) {
+            ('/', Some('*')) => {
+                i += 2;
+                while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+                    i += 1;
+                }
+                i = (i + 2).min(chars.len());
+                emit(&mut out, gap);
+                out.extend(&chars[start..i]);
+                gap = Gap::Space;
+                after_line_comment = false;
+                continue;
+            }
+            ('/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                emit(&mut out, gap);
+                out.extend(&chars[start..i]);
+                gap = Gap::Newline;
+                after_line_comment = true;
+                continue;
+            }
+            _ => (),
+        }
+        match c {
+            '<' | '[' => {
+                emit(&mut out, gap);
+                out.push(c);
+                gap = Gap::None;
+                i += 1;
+            }
+            '>' | ']' | ',' => {
+                if after_line_comment {
+                    emit(&mut out, Gap::Newline);
+                }
+                out.push(c);
+                gap = Gap::Space;
+                i += 1;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+                emit(&mut out, gap);
+                out.extend(&chars[start..i.min(chars.len())]);
+                gap = Gap::Space;
+            }
+            _ => {
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !"<>[],\"".contains(chars[i])
+                    && !(chars[i] == '/' && matches!(chars.get(i + 1), Some('*') | Some('/')))
+                {
+                    i += 1;
+                }
+                emit(&mut out, gap);
+                out.extend(&chars[start..i]);
+                gap = Gap::Space;
+            }
+        }
+        after_line_comment = false;
+    }
+    if after_line_comment {
+        emit(&mut out, Gap::Newline);
+    }
+    out
+}
+
+impl<'a> Printer<'a> {
+    // The source of a token without the newline some tokens swallow.
+    fn source(&self, span: &Span) -> &'a str {
+        self.text[span.start..span.end].trim_end()
+    }
+
+    fn indent(&self) -> String {
+        "\t".repeat(self.depth)
+    }
+
+    fn push(&mut self, s: String) {
+        let indent = self.indent();
+        self.out.push(format!("{}{}", indent, s));
+    }
+
+    // Block comments keep their inner layout; only the indentation they
+    // started with is swapped for the new one.
+    fn comment(&mut self, span: &Span, text: &str) {
+        let line_start = span.start + 1 - span.column;
+        let old = &self.text[line_start..span.start];
+        let old = if old.trim().is_empty() { old } else { "" };
+        let indent = self.indent();
+        let mut lines = text.lines();
+        let mut s = lines.next().unwrap_or("").to_string();
+        for l in lines {
+            s.push('\n');
+            match l.strip_prefix(old) {
+                Some(rest) if !old.is_empty() => {
+                    s.push_str(&indent);
+                    s.push_str(rest);
+                }
+                _ => s.push_str(l),
+            }
+        }
+        self.push(s);
+    }
+
+    fn property(&mut self, span: &Span, name: &str) -> String {
+        let source = self.source(span);
+        let value = source
+            .splitn(2, '=')
+            .nth(1)
+            .unwrap_or("")
+            .trim_end()
+            .trim_end_matches(';');
+        let indent = format!("{}{}", self.indent(), " ".repeat(name.len() + 3));
+        format!("{} = {};", name, format_value(value, &indent))
+    }
+
+    fn run(&mut self, tokens: &[(DTInfo, Span)]) {
+        let mut last: Option<(usize, bool)> = None;
+        for (token, span) in tokens {
+            let line = span.line;
+            if let DTInfo::EOF = token {
+                break;
+            }
+            if let (DTInfo::Comment(c), Some((end, _))) = (token, last) {
+                if end == line && !self.out.is_empty() {
+                    let i = self.out.len() - 1;
+                    self.out[i].push(' ');
+                    self.out[i].push_str(c);
+                    last = Some((span.end_line, false));
+                    continue;
+                }
+            }
+            if let DTInfo::NodeEnd = token {
+                self.depth = self.depth.saturating_sub(1);
+            }
+            // Keep one blank line where the source had any, except just
+            // inside braces.
+            if let Some((end, opened)) = last {
+                if line > end + 1 && !opened && !matches!(token, DTInfo::NodeEnd) {
+                    self.out.push(String::new());
+                }
+            }
+            let mut opened = false;
+            match token {
+                DTInfo::Comment(c) => self.comment(span, c),
+                DTInfo::Include(_) | DTInfo::Directive(..) => {
+                    let s = collapse(self.source(span));
+                    self.push(s);
+                }
+                DTInfo::Define(..) => {
+                    let s = self.source(span).trim().to_string();
+                    self.push(s);
+                }
+                DTInfo::Node(label, name) => {
+                    let s = match label {
+                        Some(l) => format!("{}: {} {{", l, name),
+                        None => format!("{} {{", name),
+                    };
+                    self.push(s);
+                    self.depth += 1;
+                    opened = true;
+                }
+                DTInfo::RefNode(label) => {
+                    self.push(format!("&{} {{", label));
+                    self.depth += 1;
+                    opened = true;
+                }
+                DTInfo::NodeEnd => self.push("};".to_string()),
+                DTInfo::Property(name, None) => self.push(format!("{};", name)),
+                DTInfo::Property(name, Some(_)) => {
+                    let s = self.property(span, name);
+                    self.push(s);
+                }
+                DTInfo::EOF => (),
+            }
+            last = Some((span.end_line, opened));
+        }
+    }
+}
+
+pub fn format(text: &str) -> Result<String, DTError> {
+    let tokens = lex(text)?;
+    let mut printer = Printer {
+        text,
+        out: Vec::new(),
+        depth: 0,
+    };
+    printer.run(&tokens);
+    let mut s = printer.out.join("\n");
+    s.push('\n');
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::dt_value::{parse, Value};
+    use crate::{load_str, Options};
+
+    const MESSY: &str = "/dts-v1/;
+/ {
+  model=\"board  name\" ;
+    #address-cells = < 1 >;
+  #size-cells=<1>;
+
+	uart0: serial@1000 { compatible = \"v,uart\" ,\"v,base\";
+     reg = <0x1000 0x100>,
+           <0x2000   0x10>;   // two windows
+     /* keep */ status = \"okay\";
+	};
+  leds { led { gpios = < &uart0 3 0 > ; label = \"a  b\"; }; };
+};
+&uart0 { clock-frequency = < 3 >; };
+";
+
+    type Tree = Vec<(Vec<String>, Vec<(String, Vec<Value>)>)>;
+
+    fn tree(text: &str) -> Tree {
+        let root = load_str("a.dts", text, &Options::default()).unwrap();
+        root.nodes()
+            .into_iter()
+            .map(|(path, node)| {
+                let properties = node
+                    .properties()
+                    .into_iter()
+                    .map(|(n, v)| (n.clone(), v.as_deref().map(parse).unwrap_or_default()))
+                    .collect();
+                (path, properties)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let once = format(MESSY).unwrap();
+        let twice = format(&once).unwrap();
+        assert_eq!(once, twice);
+        assert_eq!(tree(&once), tree(MESSY));
+        assert!(once.contains("\t\treg = <0x1000 0x100>,\n"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::dt_phandle::{specifiers, Spec};
+use crate::dt_value::{strings, Cell};
+use crate::root::{path_string, Root};
+use std::collections::BTreeMap;
+
+pub struct GpioUse {
+    pub controller: Vec<String>,
+    pub pin: u32,
+    pub flags: Option<u32>,
+    pub consumer: Vec<String>,
+    pub property: String,
+    pub enabled: bool,
+}
+
+fn is_gpio_property(name: &str) -> bool {
+    name == "gpios" || name == "gpio" || name.ends_with("-gpios") || name.ends_with("-gpio")
+}
+
+fn flag_names(flags: u32) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    names.push(if flags & 0x1 != 0 {
+        "active-low"
+    } else {
+        "active-high"
+    });
+    match flags & 0x6 {
+        0x6 => names.push("open-drain"),
+        0x2 => names.push("open-source"),
+        _ => (),
+    }
+    if flags & 0x8 != 0 {
+        names.push("sleep-may-lose-value");
+    }
+    if flags & 0x10 != 0 {
+        names.push("pull-up");
+    }
+    if flags & 0x20 != 0 {
+        names.push("pull-down");
+    }
+    names
+}
+
+fn push(found: &mut Vec<GpioUse>, root: &Root, consumer: &[String], property: &str, s: Spec) {
+    if let Some(pin) = s.args.first() {
+        found.push(GpioUse {
+            pin: *pin,
+            flags: s.args.get(1).cloned(),
+            enabled: root.is_enabled(consumer),
+            controller: s.target,
+            consumer: consumer.to_vec(),
+            property: property.to_string(),
+        });
+    }
+}
+
+pub fn uses(root: &Root) -> Vec<GpioUse> {
+    let mut found = Vec::new();
+    for (path, node) in root.nodes() {
+        // A hog's gpios have no phandle, the controller is its parent.
+        if node.has_property("gpio-hog") && path.len() > 1 {
+            let controller = path[..path.len() - 1].to_vec();
+            let cells = root
+                .get_node(&controller)
+                .and_then(|c| root.cell_value(c, "#gpio-cells"))
+                .unwrap_or(2) as usize;
+            let nums = root.cells(node.property("gpios").unwrap_or(&None));
+            let mode = ["output-high", "output-low", "input"]
+                .iter()
+                .find(|m| node.has_property(m))
+                .map(|m| format!("gpio-hog {}", m))
+                .unwrap_or_else(|| "gpio-hog".to_string());
+            for chunk in nums.chunks(cells.max(1)) {
+                let args = chunk
+                    .iter()
+                    .filter_map(|c| match c {
+                        Cell::Num(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                let spec = Spec {
+                    target: controller.clone(),
+                    args,
+                };
+                push(&mut found, root, &path, &mode, spec);
+            }
+            continue;
+        }
+        for (name, raw) in node.properties() {
+            if !is_gpio_property(name) {
+                continue;
+            }
+            for s in specifiers(root, raw, "#gpio-cells") {
+                match s {
+                    Ok(Some(s)) => push(&mut found, root, &path, name, s),
+                    Ok(None) => (),
+                    Err(e) => eprintln!("warning: {}: {}: {}", path_string(&path), name, e),
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn print_gpios(root: &Root) {
+    let mut by_controller: BTreeMap<Vec<String>, Vec<GpioUse>> = BTreeMap::new();
+    for u in uses(root) {
+        by_controller
+            .entry(u.controller.clone())
+            .or_insert_with(Vec::new)
+            .push(u);
+    }
+    for (controller, mut pins) in by_controller {
+        pins.sort_by_key(|u| u.pin);
+        let labels = root.labels_for(&controller);
+        let line_names = root
+            .get_node(&controller)
+            .and_then(|n| n.property("gpio-line-names"))
+            .map(strings)
+            .unwrap_or_default();
+        if labels.is_empty() {
+            println!("{}", path_string(&controller));
+        } else {
+            println!("{} ({})", path_string(&controller), labels.join(", "));
+        }
+        for u in &pins {
+            let mut line = format!(
+                "    {:<4} {} {}",
+                u.pin,
+                path_string(&u.consumer),
+                u.property
+            );
+            if let Some(f) = u.flags {
+                line.push_str(&format!(" {}", flag_names(f).join(" ")));
+            }
+            if let Some(n) = line_names.get(u.pin as usize).filter(|n| !n.is_empty()) {
+                line.push_str(&format!(" \"{}\"", n));
+            }
+            if !u.enabled {
+                line.push_str(" (disabled)");
+            }
+            println!("{}", line);
+        }
+    }
+    for c in conflicts(root) {
+        eprintln!("warning: {}", c);
+    }
+}
+
+// Pins claimed by more than one enabled consumer.
+pub fn conflicts(root: &Root) -> Vec<String> {
+    let mut enabled: Vec<GpioUse> = uses(root).into_iter().filter(|u| u.enabled).collect();
+    enabled.sort_by(|a, b| (&a.controller, a.pin).cmp(&(&b.controller, b.pin)));
+    let mut found = Vec::new();
+    for (i, a) in enabled.iter().enumerate() {
+        let same = |b: &&GpioUse| b.controller == a.controller && b.pin == a.pin;
+        for b in enabled[i + 1..].iter().filter(same) {
+            found.push(format!(
+                "pin {} of {} claimed by both {} {} and {} {}",
+                a.pin,
+                path_string(&a.controller),
+                path_string(&a.consumer),
+                a.property,
+                path_string(&b.consumer),
+                b.property
+            ));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::conflicts;
+    use crate::{load_str, Options};
+
+    #[test]
+    fn pins_claimed_twice() {
+        let dts = "/dts-v1/;\n/ {\n\
+            \tgpio: gpio { gpio-controller; #gpio-cells = <2>;\n\
+            \t\thog { gpio-hog; gpios = <3 0>; output-high; };\n\t};\n\
+            \tled { gpios = <&gpio 3 1>; };\n\
+            \tkey { gpios = <&gpio 4 0>; };\n\
+            \toff { gpios = <&gpio 4 0>; status = \"disabled\"; };\n\
+            \tspi { cs-gpios = <&gpio 5 0>, <&gpio 5 0>; };\n};\n";
+        let root = load_str("a.dts", dts, &Options::default()).unwrap();
+        assert_eq!(
+            conflicts(&root),
+            vec![
+                "pin 3 of /gpio claimed by both /gpio/hog gpio-hog output-high and /led gpios",
+                "pin 5 of /gpio claimed by both /spi cs-gpios and /spi cs-gpios",
+            ]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::dt_address::decode_reg;
+use crate::dt_irq::{is_gic, resolve, Irq};
+use crate::dt_status::{own_status, Status};
+use crate::dt_value::{parse, Cell, Value};
+use crate::root::{path_string, Root};
+use std::collections::BTreeMap;
+
+// Names are lowercased with anything but letters and digits made `_`, as
+// Zephyr does: `arm,gic-400` is `arm_gic_400`.
+fn token(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+// `DT_N` for `/`, `DT_N_S_soc_S_serial_1000` for `/soc/serial@1000`.
+fn node_id(path: &[String]) -> String {
+    let mut id = String::from("DT_N");
+    for p in path.iter().skip(1) {
+        id.push_str("_S_");
+        id.push_str(&token(p));
+    }
+    id
+}
+
+fn c_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn define(out: &mut String, name: &str, value: &str) {
+    let line = format!("#define {} {}", name, value);
+    out.push_str(line.trim_end());
+    out.push('\n');
+}
+
+// A property's parts as C expressions: numbers, strings and the identifiers
+// of nodes referred to.
+fn elements(root: &Root, raw: &str) -> Vec<String> {
+    let node = |label: &str| match root.label_path(label) {
+        Some(p) => node_id(p),
+        None => format!("/* &{} */ 0", label),
+    };
+    let mut found = Vec::new();
+    for v in parse(raw) {
+        match v {
+            Value::Cells(cells) => {
+                for c in cells {
+                    found.push(match c {
+                        Cell::Num(n) => format!("{}", n),
+                        Cell::Ref(l) => node(&l),
+                        Cell::Symbol(s) => match root.expand(&s) {
+                            Some(n) => format!("{}", n),
+                            None => s,
+                        },
+                    });
+                }
+            }
+            Value::Str(s) => found.push(c_string(&s)),
+            Value::Ref(l) => found.push(node(&l)),
+        }
+    }
+    found
+}
+
+fn property(out: &mut String, root: &Root, id: &str, name: &str, raw: &Option<String>) {
+    let prefix = format!("{}_P_{}", id, token(name));
+    define(out, &format!("{}_EXISTS", prefix), "1");
+    let raw = match raw {
+        Some(r) => r,
+        None => return define(out, &prefix, "1"),
+    };
+    let parts = elements(root, raw);
+    let numeric = parts.iter().all(|p| p.parse::<u64>().is_ok());
+    match parts.as_slice() {
+        [one] => define(out, &prefix, one),
+        _ if numeric => define(out, &prefix, &format!("{{{}}}", parts.join(", "))),
+        _ => (),
+    }
+    for (i, p) in parts.iter().enumerate() {
+        define(out, &format!("{}_IDX_{}", prefix, i), p);
+        define(out, &format!("{}_IDX_{}_EXISTS", prefix, i), "1");
+    }
+    define(out, &format!("{}_LEN", prefix), &parts.len().to_string());
+}
+
+// What each specifier cell means, by the convention the `interrupts` report
+// follows.
+fn irq_cells(root: &Root, irq: &Irq) -> Vec<String> {
+    let names: &[&str] = match irq.specifier.len() {
+        3 if is_gic(root, &irq.controller) => &["type", "irq", "flags"],
+        2 => &["irq", "flags"],
+        1 => &["irq"],
+        _ => &[],
+    };
+    (0..irq.specifier.len())
+        .map(|i| match names.get(i) {
+            Some(n) => n.to_string(),
+            None => format!("cell{}", i),
+        })
+        .collect()
+}
+
+fn node(out: &mut String, root: &Root, path: &[String], ord: usize) {
+    let node = match root.get_node(path) {
+        Some(n) => n,
+        None => return,
+    };
+    let id = node_id(path);
+    out.push_str(&format!("\n/* {} */\n", path_string(path)));
+    define(out, &format!("{}_EXISTS", id), "1");
+    define(out, &format!("{}_ORD", id), &ord.to_string());
+    define(out, &format!("{}_PATH", id), &c_string(&path_string(path)));
+    define(out, &format!("{}_FULL_NAME", id), &c_string(&node.name));
+    if path.len() > 1 {
+        define(
+            out,
+            &format!("{}_PARENT", id),
+            &node_id(&path[..path.len() - 1]),
+        );
+    }
+    let children: Vec<String> = node
+        .children()
+        .iter()
+        .map(|c| {
+            let mut p = path.to_vec();
+            p.push(c.name.clone());
+            format!("fn({})", node_id(&p))
+        })
+        .collect();
+    define(
+        out,
+        &format!("{}_FOREACH_CHILD(fn)", id),
+        &children.join(" "),
+    );
+    let status = match own_status(root, path) {
+        Status::Okay => "okay".to_string(),
+        Status::Disabled => "disabled".to_string(),
+        Status::Reserved => "reserved".to_string(),
+        Status::Fail(s) => token(&s),
+    };
+    define(out, &format!("{}_STATUS_{}", id, status), "1");
+    for c in root.strings(node, "compatible").unwrap_or_default() {
+        define(out, &format!("{}_COMPAT_MATCHES_{}", id, token(&c)), "1");
+    }
+    if let Some(Ok(regions)) = decode_reg(root, path) {
+        define(out, &format!("{}_REG_NUM", id), &regions.len().to_string());
+        for (i, r) in regions.iter().enumerate() {
+            let reg = format!("{}_REG_IDX_{}", id, i);
+            define(out, &format!("{}_EXISTS", reg), "1");
+            define(
+                out,
+                &format!("{}_VAL_ADDRESS", reg),
+                &format!("{:#x}", r.address),
+            );
+            define(out, &format!("{}_VAL_SIZE", reg), &format!("{:#x}", r.size));
+        }
+    }
+    if let Some(resolved) = resolve(root, path) {
+        let irqs: Vec<Irq> = resolved.irqs.into_iter().filter_map(|i| i.ok()).collect();
+        define(out, &format!("{}_IRQ_NUM", id), &irqs.len().to_string());
+        for (i, irq) in irqs.iter().enumerate() {
+            let prefix = format!("{}_IRQ_IDX_{}", id, i);
+            define(out, &format!("{}_EXISTS", prefix), "1");
+            define(
+                out,
+                &format!("{}_CONTROLLER", prefix),
+                &node_id(&irq.controller),
+            );
+            for (name, value) in irq_cells(root, irq).iter().zip(&irq.specifier) {
+                define(out, &format!("{}_VAL_{}", prefix, name), &value.to_string());
+            }
+        }
+    }
+    for (name, raw) in node.properties() {
+        property(out, root, &id, name, raw);
+    }
+}
+
+// A node named by a string in /aliases or /chosen: a path or a label.
+fn named(root: &Root, raw: &Option<String>) -> Option<Vec<String>> {
+    match parse(raw.as_ref()?).first()? {
+        Value::Str(s) if s.starts_with('/') => {
+            let path: Vec<String> = std::iter::once("/".to_string())
+                .chain(s.split('/').filter(|p| !p.is_empty()).map(String::from))
+                .collect();
+            root.get_node(&path).map(|_| path)
+        }
+        Value::Str(s) => root.label_path(s).cloned(),
+        Value::Ref(l) => root.label_path(l).cloned(),
+        Value::Cells(_) => None,
+    }
+}
+
+// C macros describing the tree the way Zephyr's devicetree_generated.h does:
+// `DT_N_S_soc_S_serial_1000` style identifiers for each node with its path,
+// parent, status, `reg`, interrupts and properties, `DT_N_NODELABEL_`,
+// `DT_N_ALIAS_` and `DT_CHOSEN_` names for them, and the okay instances of
+// each compatible.
+pub fn header(root: &Root) -> String {
+    let mut out = String::from(
+        "/*\n * Generated by mimir, do not edit.\n */\n\n\
+         #ifndef DEVICETREE_GENERATED_H\n#define DEVICETREE_GENERATED_H\n",
+    );
+    let nodes = root.nodes();
+    for (ord, (path, _)) in nodes.iter().enumerate() {
+        node(&mut out, root, path, ord);
+    }
+
+    out.push_str("\n/* Labels, aliases and chosen nodes */\n");
+    let mut labels = root.labels();
+    labels.sort();
+    for (l, p) in labels {
+        define(
+            &mut out,
+            &format!("DT_N_NODELABEL_{}", token(l)),
+            &node_id(p),
+        );
+    }
+    for (kind, at) in &[("DT_N_ALIAS", "aliases"), ("DT_CHOSEN", "chosen")] {
+        if let Some(n) = root.get_node(&["/", at]) {
+            for (name, raw) in n.properties() {
+                if let Some(p) = named(root, raw) {
+                    define(&mut out, &format!("{}_{}", kind, token(name)), &node_id(&p));
+                }
+            }
+        }
+    }
+
+    // Instances are numbered in tree order among the okay nodes.
+    let mut instances: BTreeMap<String, Vec<&Vec<String>>> = BTreeMap::new();
+    for (path, node) in &nodes {
+        if own_status(root, path) != Status::Okay {
+            continue;
+        }
+        for c in root.strings(node, "compatible").unwrap_or_default() {
+            instances.entry(token(&c)).or_default().push(path);
+        }
+    }
+    out.push_str("\n/* Instances of each compatible */\n");
+    for (c, paths) in &instances {
+        for (i, p) in paths.iter().enumerate() {
+            define(&mut out, &format!("DT_N_INST_{}_{}", i, c), &node_id(p));
+        }
+        define(&mut out, &format!("DT_COMPAT_HAS_OKAY_{}", c), "1");
+        define(
+            &mut out,
+            &format!("DT_N_INST_{}_NUM_OKAY", c),
+            &paths.len().to_string(),
+        );
+        let each: Vec<String> = paths
+            .iter()
+            .map(|p| format!("fn({})", node_id(p)))
+            .collect();
+        define(
+            &mut out,
+            &format!("DT_FOREACH_OKAY_{}(fn)", c),
+            &each.join(" "),
+        );
+        let each: Vec<String> = (0..paths.len()).map(|i| format!("fn({})", i)).collect();
+        define(
+            &mut out,
+            &format!("DT_FOREACH_OKAY_INST_{}(fn)", c),
+            &each.join(" "),
+        );
+    }
+    out.push_str("\n#endif /* DEVICETREE_GENERATED_H */\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{c_string, header};
+    use crate::{load_str, Options};
+
+    const DTS: &str = "/dts-v1/;
+/ {
+\t#address-cells = <1>;
+\t#size-cells = <1>;
+\taliases { serial0 = &uart0; };
+\tgic: interrupt-controller@1000 {
+\t\tcompatible = \"arm,gic-400\";
+\t\treg = <0x1000 0x100>;
+\t\tinterrupt-controller;
+\t\t#interrupt-cells = <3>;
+\t};
+\tuart0: serial@2000 {
+\t\tcompatible = \"v,uart\";
+\t\treg = <0x2000 0x100>;
+\t\tinterrupt-parent = <&gic>;
+\t\tinterrupts = <0 5 4>;
+\t};
+\tserial@3000 { compatible = \"v,uart\"; reg = <0x3000 0x100>; status = \"disabled\"; };
+\tserial@4000 { compatible = \"v,uart\"; reg = <0x4000 0x100>; };
+};
+";
+
+    #[test]
+    fn macros() {
+        let root = load_str("a.dts", DTS, &Options::default()).unwrap();
+        let h = header(&root);
+        let lines: Vec<&str> = h.lines().collect();
+        for expected in &[
+            "#define DT_N_S_serial_2000_ORD 3",
+            "#define DT_N_S_serial_2000_PATH \"/serial@2000\"",
+            "#define DT_N_S_serial_2000_STATUS_okay 1",
+            "#define DT_N_S_serial_3000_STATUS_disabled 1",
+            "#define DT_N_S_serial_2000_REG_NUM 1",
+            "#define DT_N_S_serial_2000_REG_IDX_0_VAL_ADDRESS 0x2000",
+            "#define DT_N_S_serial_2000_REG_IDX_0_VAL_SIZE 0x100",
+            "#define DT_N_S_serial_2000_IRQ_IDX_0_CONTROLLER DT_N_S_interrupt_controller_1000",
+            "#define DT_N_S_serial_2000_IRQ_IDX_0_VAL_irq 5",
+            "#define DT_N_S_serial_2000_IRQ_IDX_0_VAL_flags 4",
+            "#define DT_N_S_serial_2000_P_compatible \"v,uart\"",
+            "#define DT_N_NODELABEL_uart0 DT_N_S_serial_2000",
+            "#define DT_N_ALIAS_serial0 DT_N_S_serial_2000",
+            "#define DT_N_INST_0_v_uart DT_N_S_serial_2000",
+            "#define DT_N_INST_1_v_uart DT_N_S_serial_4000",
+            "#define DT_N_INST_v_uart_NUM_OKAY 2",
+            "#define DT_FOREACH_OKAY_INST_v_uart(fn) fn(0) fn(1)",
+        ] {
+            assert!(lines.contains(expected), "missing {}", expected);
+        }
+        assert!(!h.contains("DT_N_INST_2_v_uart"));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(c_string(r#"C:\say"hi""#), r#""C:\\say\"hi\"""#);
+    }
+}
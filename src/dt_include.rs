@@ -0,0 +1,129 @@
+use crate::dt_dot::Graph;
+use crate::dt_load::Source;
+use std::fs;
+use std::io;
+
+fn includes<'a>(sources: &'a [Source], file: &str) -> &'a [String] {
+    match sources.iter().find(|s| s.file == file) {
+        Some(s) => &s.includes,
+        None => &[],
+    }
+}
+
+fn print_includes(sources: &[Source], file: &str, depth: usize, seen: &mut Vec<String>) {
+    for i in includes(sources, file) {
+        if seen.contains(i) {
+            println!("{}{} (again)", "    ".repeat(depth), i);
+            continue;
+        }
+        println!("{}{}", "    ".repeat(depth), i);
+        seen.push(i.clone());
+        print_includes(sources, i, depth + 1, seen);
+    }
+}
+
+// Every file in include order; a file pulled in more than once is expanded
+// only the first time.
+pub fn print_tree(sources: &[Source]) {
+    let first = match sources.first() {
+        Some(s) => &s.file,
+        None => return,
+    };
+    println!("{}", first);
+    print_includes(sources, first, 1, &mut vec![first.clone()]);
+}
+
+pub fn dot(sources: &[Source]) -> Graph {
+    let mut g = Graph::new("includes");
+    for s in sources {
+        g.node(s.file.as_str(), s.file.as_str());
+    }
+    for s in sources {
+        for (n, i) in s.includes.iter().enumerate() {
+            g.edge(s.file.clone(), i.clone(), Some((n + 1).to_string()));
+        }
+    }
+    g
+}
+
+// Make treats spaces, `#` and `$` specially in file names.
+fn escape(file: &str) -> String {
+    file.replace(' ', "\\ ")
+        .replace('#', "\\#")
+        .replace('$', "$$")
+}
+
+// A rule making `target` depend on the input and everything it includes,
+// in the format of `cpp -MD`.
+pub fn depfile(target: &str, sources: &[Source]) -> String {
+    let mut out = format!("{}:", escape(target));
+    let mut width = out.len();
+    for s in sources {
+        let f = escape(&s.file);
+        if width + f.len() + 1 > 78 {
+            out.push_str(" \\\n");
+            width = 0;
+        }
+        out.push(' ');
+        out.push_str(&f);
+        width += f.len() + 1;
+    }
+    out.push('\n');
+    out
+}
+
+pub fn write_depfile(path: &str, target: &str, sources: &[Source]) -> io::Result<()> {
+    fs::write(path, depfile(target, sources))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::depfile;
+    use crate::dt_load::{sources, Options, Source};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn rule_lists_every_source_and_wraps() {
+        let dir = "arch/arm64/boot/dts/vendor";
+        let files: HashMap<String, String> = vec![
+            (
+                format!("{}/board.dts", dir),
+                "/dts-v1/;\n#include \"soc.dtsi\"\n/ {\n};\n".to_string(),
+            ),
+            (
+                format!("{}/soc.dtsi", dir),
+                "#include \"soc-pinctrl.dtsi\"\n#include \"soc-clocks.dtsi\"\n".to_string(),
+            ),
+            (format!("{}/soc-pinctrl.dtsi", dir), "/ {\n};\n".to_string()),
+            (format!("{}/soc-clocks.dtsi", dir), "/ {\n};\n".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let read = |f: &str| files.get(f).cloned().ok_or_else(|| "missing".to_string());
+        let first = format!("{}/board.dts", dir);
+        let found = sources(&first, &read, &Options::default()).unwrap();
+        assert_eq!(
+            depfile("board.dtb", &found),
+            "board.dtb: arch/arm64/boot/dts/vendor/board.dts \\\n \
+             arch/arm64/boot/dts/vendor/soc.dtsi \\\n \
+             arch/arm64/boot/dts/vendor/soc-pinctrl.dtsi \\\n \
+             arch/arm64/boot/dts/vendor/soc-clocks.dtsi\n"
+        );
+    }
+
+    #[test]
+    fn make_specials_are_escaped() {
+        let source = |f: &str| Source {
+            file: f.to_string(),
+            text: String::new(),
+            tokens: Rc::new(Vec::new()),
+            includes: Vec::new(),
+        };
+        let found = vec![source("my board.dts"), source("#1.dtsi"), source("$x.dtsi")];
+        assert_eq!(
+            depfile("out dir/a.dtb", &found),
+            "out\\ dir/a.dtb: my\\ board.dts \\#1.dtsi $$x.dtsi\n"
+        );
+    }
+}
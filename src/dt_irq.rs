@@ -0,0 +1,560 @@
+use crate::dt_value::{strings, Cell};
+use crate::root::{path_string, DTNode, Root};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Irq {
+    pub controller: Vec<String>,
+    pub specifier: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum IrqError {
+    NoParent,
+    NoCells(Vec<String>),
+    BadLength(Vec<String>, usize, u32),
+    Unresolved(String),
+    NotController(Vec<String>),
+    NoMapEntry(Vec<String>, Vec<u32>),
+    TooDeep,
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrqError::NoParent => write!(f, "no interrupt-parent"),
+            IrqError::NoCells(p) => write!(f, "{} has no #interrupt-cells", path_string(p)),
+            IrqError::BadLength(p, n, c) => write!(
+                f,
+                "{} cells do not fit #interrupt-cells = <{}> of {}",
+                n,
+                c,
+                path_string(p)
+            ),
+            IrqError::Unresolved(s) => write!(f, "cannot resolve {}", s),
+            IrqError::NotController(p) => write!(
+                f,
+                "{} is neither an interrupt-controller nor has an interrupt-map",
+                path_string(p)
+            ),
+            IrqError::NoMapEntry(p, s) => write!(
+                f,
+                "no interrupt-map entry in {} for {:x?}",
+                path_string(p),
+                s
+            ),
+            IrqError::TooDeep => write!(f, "interrupt-map nesting too deep"),
+        }
+    }
+}
+
+pub struct Resolved {
+    pub path: Vec<String>,
+    pub irqs: Vec<Result<Irq, IrqError>>,
+}
+
+fn numbers(cells: &[Cell]) -> Result<Vec<u32>, IrqError> {
+    cells
+        .iter()
+        .map(|c| match c {
+            Cell::Num(n) => Ok(*n),
+            Cell::Ref(r) => Err(IrqError::Unresolved(format!("&{}", r))),
+            Cell::Symbol(s) => Err(IrqError::Unresolved(s.clone())),
+        })
+        .collect()
+}
+
+fn phandle(root: &Root, cell: &Cell) -> Result<Vec<String>, IrqError> {
+    match root.resolve_phandle(cell) {
+        Some(p) => Ok(p),
+        None => Err(IrqError::Unresolved(format!("{:?}", cell))),
+    }
+}
+
+fn interrupt_parent(root: &Root, path: &[String]) -> Option<Vec<String>> {
+    let node = root.get_node(path)?;
+    match node.property("interrupt-parent") {
+        Some(raw) => root.resolve_phandle(root.cells(raw).first()?),
+        None if path.len() > 1 => Some(path[..path.len() - 1].to_vec()),
+        None => None,
+    }
+}
+
+// Same walk as the kernel's of_irq_find_parent: follow interrupt-parent, or
+// the tree parent when there is none, until a node with #interrupt-cells.
+pub fn find_parent(root: &Root, path: &[String]) -> Option<Vec<String>> {
+    let mut p = interrupt_parent(root, path)?;
+    for _ in 0..64 {
+        if root.get_node(&p)?.has_property("#interrupt-cells") {
+            return Some(p);
+        }
+        p = interrupt_parent(root, &p)?;
+    }
+    None
+}
+
+fn interrupt_cells(root: &Root, path: &[String]) -> Result<u32, IrqError> {
+    match root
+        .get_node(path)
+        .and_then(|n| root.cell_value(n, "#interrupt-cells"))
+    {
+        Some(c) => Ok(c),
+        None => Err(IrqError::NoCells(path.to_vec())),
+    }
+}
+
+// interrupt-map matching uses the nexus' own #address-cells or, failing that,
+// the first ancestor's, defaulting to 2 like of_irq_parse_raw.
+fn map_address_cells(root: &Root, path: &[String]) -> u32 {
+    (1..=path.len())
+        .rev()
+        .find_map(|i| {
+            root.get_node(&path[..i])
+                .and_then(|n| root.cell_value(n, "#address-cells"))
+        })
+        .unwrap_or(2)
+}
+
+fn unit_address(root: &Root, node: &DTNode, cells: u32) -> Vec<u32> {
+    let reg = match node.property("reg") {
+        Some(raw) => numbers(&root.cells(raw)).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    (0..cells as usize)
+        .map(|i| *reg.get(i).unwrap_or(&0))
+        .collect()
+}
+
+fn route(
+    root: &Root,
+    parent: Vec<String>,
+    unit: Vec<u32>,
+    spec: Vec<u32>,
+) -> Result<Irq, IrqError> {
+    let (mut parent, mut unit, mut spec) = (parent, unit, spec);
+    for _ in 0..32 {
+        let node = match root.get_node(&parent) {
+            Some(n) => n,
+            None => return Err(IrqError::Unresolved(path_string(&parent))),
+        };
+        let cells = interrupt_cells(root, &parent)?;
+        if spec.len() != cells as usize {
+            return Err(IrqError::BadLength(parent, spec.len(), cells));
+        }
+        let map = match node.property("interrupt-map") {
+            Some(m) => root.cells(m),
+            None if node.has_property("interrupt-controller") => {
+                return Ok(Irq {
+                    controller: parent,
+                    specifier: spec,
+                })
+            }
+            None => return Err(IrqError::NotController(parent)),
+        };
+        // The unit address was read with this nexus' #address-cells, by
+        // the caller or from the previous map entry.
+        let ac = unit.len();
+        let ic = cells as usize;
+        let mask = match node.property("interrupt-map-mask") {
+            Some(m) => numbers(&root.cells(m))?,
+            None => vec![0xffff_ffff; ac + ic],
+        };
+        let key: Vec<u32> = unit.iter().chain(spec.iter()).cloned().collect();
+        let mut i = 0;
+        let mut next = None;
+        while i + ac + ic < map.len() {
+            let child = numbers(&map[i..i + ac + ic])?;
+            let new_parent = phandle(root, &map[i + ac + ic])?;
+            let new_node = match root.get_node(&new_parent) {
+                Some(n) => n,
+                None => return Err(IrqError::Unresolved(path_string(&new_parent))),
+            };
+            let pac = root.cell_value(new_node, "#address-cells").unwrap_or(0) as usize;
+            let pic = interrupt_cells(root, &new_parent)? as usize;
+            let start = i + ac + ic + 1;
+            if start + pac + pic > map.len() {
+                break;
+            }
+            let matched = (0..ac + ic)
+                .all(|j| (key.get(j).unwrap_or(&0) ^ child[j]) & mask.get(j).unwrap_or(&0) == 0);
+            if matched {
+                next = Some((
+                    new_parent,
+                    numbers(&map[start..start + pac])?,
+                    numbers(&map[start + pac..start + pac + pic])?,
+                ));
+                break;
+            }
+            i = start + pac + pic;
+        }
+        match next {
+            Some((p, u, s)) => {
+                parent = p;
+                unit = u;
+                spec = s;
+            }
+            None => return Err(IrqError::NoMapEntry(parent, key)),
+        }
+    }
+    Err(IrqError::TooDeep)
+}
+
+pub fn resolve(root: &Root, path: &[String]) -> Option<Resolved> {
+    let node = root.get_node(path)?;
+    let mut irqs = Vec::new();
+    if let Some(raw) = node.property("interrupts-extended") {
+        let cells = root.cells(raw);
+        let mut i = 0;
+        while i < cells.len() {
+            let parent = match phandle(root, &cells[i]) {
+                Ok(p) => p,
+                Err(e) => {
+                    irqs.push(Err(e));
+                    break;
+                }
+            };
+            let n = match interrupt_cells(root, &parent) {
+                Ok(n) => n as usize,
+                Err(e) => {
+                    irqs.push(Err(e));
+                    break;
+                }
+            };
+            let end = (i + 1 + n).min(cells.len());
+            let unit = unit_address(root, node, map_address_cells(root, &parent));
+            irqs.push(numbers(&cells[i + 1..end]).and_then(|s| route(root, parent, unit, s)));
+            i = end;
+        }
+    } else if let Some(raw) = node.property("interrupts") {
+        let cells = root.cells(raw);
+        let parent = match find_parent(root, path) {
+            Some(p) => p,
+            None => {
+                irqs.push(Err(IrqError::NoParent));
+                return Some(Resolved {
+                    path: path.to_vec(),
+                    irqs,
+                });
+            }
+        };
+        match interrupt_cells(root, &parent) {
+            Ok(n) if n == 0 || cells.len() % n as usize != 0 => {
+                irqs.push(Err(IrqError::BadLength(parent, cells.len(), n)))
+            }
+            Ok(n) => {
+                let unit = unit_address(root, node, map_address_cells(root, &parent));
+                for spec in cells.chunks(n as usize) {
+                    irqs.push(
+                        numbers(spec).and_then(|s| route(root, parent.clone(), unit.clone(), s)),
+                    );
+                }
+            }
+            Err(e) => irqs.push(Err(e)),
+        }
+    } else {
+        return None;
+    }
+    Some(Resolved {
+        path: path.to_vec(),
+        irqs,
+    })
+}
+
+pub fn resolve_all(root: &Root) -> Vec<Resolved> {
+    root.nodes()
+        .into_iter()
+        .filter_map(|(p, _)| resolve(root, &p))
+        .collect()
+}
+
+fn trigger(flags: u32) -> &'static str {
+    match flags & 0xf {
+        1 => "edge-rising",
+        2 => "edge-falling",
+        3 => "edge-both",
+        4 => "level-high",
+        8 => "level-low",
+        _ => "none",
+    }
+}
+
+pub fn is_gic(root: &Root, controller: &[String]) -> bool {
+    match root
+        .get_node(controller)
+        .and_then(|n| n.property("compatible"))
+    {
+        Some(c) => strings(c).iter().any(|c| {
+            c.starts_with("arm,gic") || (c.starts_with("arm,cortex-a") && c.ends_with("-gic"))
+        }),
+        None => false,
+    }
+}
+
+pub fn describe(root: &Root, irq: &Irq) -> String {
+    match irq.specifier.as_slice() {
+        [kind, n, flags, ..] if is_gic(root, &irq.controller) => {
+            let (name, base) = match kind {
+                0 => ("SPI", 32),
+                1 => ("PPI", 16),
+                2 => ("ESPI", 4096),
+                3 => ("EPPI", 1056),
+                _ => ("?", 0),
+            };
+            match n.checked_add(base) {
+                Some(hwirq) => format!("{} {} (hwirq {}) {}", name, n, hwirq, trigger(*flags)),
+                None => format!("{} {} {}", name, n, trigger(*flags)),
+            }
+        }
+        [n, flags] => format!("{} {}", n, trigger(*flags)),
+        [n] => format!("{}", n),
+        s => format!("{:x?}", s),
+    }
+}
+
+// The part of a specifier that names the line, without the trigger flags.
+fn line(root: &Root, irq: &Irq) -> Vec<u32> {
+    match irq.specifier.as_slice() {
+        [kind, n, _, ..] if is_gic(root, &irq.controller) => vec![*kind, *n],
+        [n, _] => vec![*n],
+        s => s.to_vec(),
+    }
+}
+
+fn at(root: &Root, path: &[String]) -> String {
+    let loc = root.get_node(path).and_then(|n| {
+        n.property_location("interrupts-extended")
+            .or_else(|| n.property_location("interrupts"))
+            .or_else(|| n.locations().first())
+    });
+    match loc {
+        Some(l) => format!("{} ({})", path_string(path), l),
+        None => path_string(path),
+    }
+}
+
+// The findings of the `irq-conflicts` report, one line each.
+pub fn conflicts(root: &Root) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut lines: BTreeMap<(Vec<String>, Vec<u32>), Vec<(Vec<String>, Irq)>> = BTreeMap::new();
+    for r in resolve_all(root) {
+        if !root.is_enabled(&r.path) {
+            continue;
+        }
+        for irq in r.irqs {
+            match irq {
+                Ok(i) => {
+                    if !root.is_enabled(&i.controller) {
+                        out.push(format!(
+                            "disabled controller: {} routes {} to {}",
+                            at(root, &r.path),
+                            describe(root, &i),
+                            path_string(&i.controller)
+                        ));
+                    }
+                    lines
+                        .entry((i.controller.clone(), line(root, &i)))
+                        .or_insert_with(Vec::new)
+                        .push((r.path.clone(), i));
+                }
+                Err(IrqError::NoParent) => {
+                    out.push(format!("no interrupt-parent: {}", at(root, &r.path)))
+                }
+                Err(_) => (),
+            }
+        }
+    }
+    for ((controller, _), users) in lines {
+        let shared = users.iter().all(|(p, _)| match root.get_node(p) {
+            Some(n) => n.has_property("shared-interrupt"),
+            None => false,
+        });
+        if shared {
+            continue;
+        }
+        for (i, (a, irq)) in users.iter().enumerate() {
+            for (b, _) in users[i + 1..].iter().filter(|(b, _)| b != a) {
+                out.push(format!(
+                    "conflict: {} and {} both use {} on {}",
+                    at(root, a),
+                    at(root, b),
+                    describe(root, irq),
+                    path_string(&controller)
+                ));
+            }
+        }
+    }
+    out
+}
+
+pub fn print_conflicts(root: &Root) {
+    for c in conflicts(root) {
+        println!("{}", c);
+    }
+}
+
+pub fn print_interrupts(root: &Root) {
+    let mut rows: Vec<(String, String, String)> = Vec::new();
+    for r in resolve_all(root) {
+        let path = path_string(&r.path);
+        for irq in r.irqs {
+            match irq {
+                Ok(i) => rows.push((path.clone(), path_string(&i.controller), describe(root, &i))),
+                Err(e) => eprintln!("warning: {}: {}", path, e),
+            }
+        }
+    }
+    let w0 = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+    let w1 = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+    for (device, controller, irq) in rows {
+        println!(
+            "{:<w0$}  {:<w1$}  {}",
+            device,
+            controller,
+            irq,
+            w0 = w0,
+            w1 = w1
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{conflicts, describe, resolve, Irq};
+    use crate::root::Root;
+    use crate::{load_str, Options};
+
+    fn tree(body: &str) -> Root {
+        let dts = format!("/dts-v1/;\n/ {{\n{}}};\n", body);
+        load_str("a.dts", &dts, &Options::default()).unwrap()
+    }
+
+    fn path(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn irqs(root: &Root, p: &[&str]) -> Vec<Irq> {
+        resolve(root, &path(p))
+            .unwrap()
+            .irqs
+            .into_iter()
+            .map(|i| i.unwrap())
+            .collect()
+    }
+
+    fn irq(controller: &[&str], specifier: &[u32]) -> Irq {
+        Irq {
+            controller: path(controller),
+            specifier: specifier.to_vec(),
+        }
+    }
+
+    const GIC: &str = "\t#address-cells = <1>;\n\t#size-cells = <1>;\n\
+        \tinterrupt-parent = <&gic>;\n\
+        \tgic: interrupt-controller@1000 {\n\t\tcompatible = \"arm,gic-400\";\n\
+        \t\tinterrupt-controller;\n\t\t#interrupt-cells = <3>;\n\t\treg = <0x1000 0x1000>;\n\t};\n\
+        \tintc: intc@2000 {\n\t\tinterrupt-controller;\n\t\t#interrupt-cells = <2>;\n\t\treg = <0x2000 0x100>;\n\t};\n";
+
+    #[test]
+    fn interrupt_parent_is_inherited() {
+        let r = tree(&format!(
+            "{}\tsoc {{\n\t\tuart@3000 {{ interrupts = <0 5 4>, <1 9 1>; }};\n\
+             \t\tbus {{ interrupt-parent = <&intc>; dev {{ interrupts = <7 8>; }}; }};\n\t}};\n",
+            GIC
+        ));
+        assert_eq!(
+            irqs(&r, &["/", "soc", "uart@3000"]),
+            vec![
+                irq(&["/", "interrupt-controller@1000"], &[0, 5, 4]),
+                irq(&["/", "interrupt-controller@1000"], &[1, 9, 1]),
+            ]
+        );
+        assert_eq!(
+            irqs(&r, &["/", "soc", "bus", "dev"]),
+            vec![irq(&["/", "intc@2000"], &[7, 8])]
+        );
+    }
+
+    #[test]
+    fn interrupts_extended() {
+        let r = tree(&format!(
+            "{}\tdev {{ interrupts-extended = <&gic 0 3 4>, <&intc 2 1>; }};\n",
+            GIC
+        ));
+        assert_eq!(
+            irqs(&r, &["/", "dev"]),
+            vec![
+                irq(&["/", "interrupt-controller@1000"], &[0, 3, 4]),
+                irq(&["/", "intc@2000"], &[2, 1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn interrupt_map_with_mask() {
+        // A PCI-style nexus: the device number in the unit address and the
+        // INTx pin pick the line, through a second nexus with no
+        // #address-cells of its own.
+        let r = tree(&format!(
+            "{}\tpci {{\n\t\t#address-cells = <3>;\n\t\t#size-cells = <2>;\n\t\t#interrupt-cells = <1>;\n\
+             \t\tinterrupt-map-mask = <0x1800 0 0 7>;\n\
+             \t\tinterrupt-map = <0x0000 0 0 1 &gic 0 10 4>, <0x0800 0 0 1 &nexus 5>;\n\
+             \t\tdev@0 {{ reg = <0x0000 0 0 0 0>; interrupts = <1>; }};\n\
+             \t\tdev@1 {{ reg = <0x0800 0 0 0 0>; interrupts = <1>; }};\n\
+             \t\tdev@2 {{ reg = <0x1000 0 0 0 0>; interrupts = <1>; }};\n\t}};\n\
+             \tnexus: nexus {{\n\t\t#interrupt-cells = <1>;\n\t\tinterrupt-map-mask = <0xff>;\n\
+             \t\tinterrupt-map = <5 &intc 6 4>;\n\t}};\n",
+            GIC
+        ));
+        assert_eq!(
+            irqs(&r, &["/", "pci", "dev@0"]),
+            vec![irq(&["/", "interrupt-controller@1000"], &[0, 10, 4])]
+        );
+        assert_eq!(
+            irqs(&r, &["/", "pci", "dev@1"]),
+            vec![irq(&["/", "intc@2000"], &[6, 4])]
+        );
+        assert!(resolve(&r, &path(&["/", "pci", "dev@2"])).unwrap().irqs[0].is_err());
+    }
+
+    #[test]
+    fn gic_hwirq() {
+        let r = tree(GIC);
+        let gic = ["/", "interrupt-controller@1000"];
+        assert_eq!(
+            describe(&r, &irq(&gic, &[0, 5, 4])),
+            "SPI 5 (hwirq 37) level-high"
+        );
+        assert_eq!(
+            describe(&r, &irq(&gic, &[1, 9, 1])),
+            "PPI 9 (hwirq 25) edge-rising"
+        );
+        assert_eq!(
+            describe(&r, &irq(&gic, &[0, 0xffff_fff0, 4])),
+            "SPI 4294967280 level-high"
+        );
+        assert_eq!(
+            describe(&r, &irq(&["/", "intc@2000"], &[3, 8])),
+            "3 level-low"
+        );
+    }
+
+    #[test]
+    fn conflicts_and_shared_lines() {
+        let r = tree(&format!(
+            "{}\ta {{ interrupts = <0 5 4>; }};\n\tb {{ interrupts = <0 5 1>; }};\n\
+             \tc {{ interrupts = <0 6 4>; shared-interrupt; }};\n\td {{ interrupts = <0 6 4>; shared-interrupt; }};\n\
+             \te {{ interrupts = <0 7 4>; status = \"disabled\"; }};\n\tf {{ interrupts = <0 7 4>; }};\n\
+             \tg {{ interrupts-extended = <&off 1>; }};\n\
+             \toff: off {{ interrupt-controller; #interrupt-cells = <1>; status = \"disabled\"; }};\n",
+            GIC
+        ));
+        assert_eq!(
+            conflicts(&r),
+            vec![
+                "disabled controller: /g (a.dts:23:6) routes 1 to /off".to_string(),
+                "conflict: /a (a.dts:17:6) and /b (a.dts:18:6) both use SPI 5 (hwirq 37) level-high on /interrupt-controller@1000".to_string(),
+            ]
+        );
+    }
+}
@@ -1,8 +1,9 @@
 extern crate logos;
 use crate::dt_lexer::DTToken::NodeStart;
 use logos::Logos;
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum DTInfo {
     Include(String),
     Directive(String, Option<String>),
@@ -12,6 +13,36 @@ pub enum DTInfo {
     Define(String, String),
     EOF,
     RefNode(String),
+    Comment(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+}
+
+fn line_index(lines: &[usize], offset: usize) -> usize {
+    match lines.binary_search(&offset) {
+        Ok(l) => l,
+        Err(l) => l - 1,
+    }
+}
+
+impl Span {
+    fn new(lines: &[usize], start: usize, end: usize) -> Self {
+        let line = line_index(lines, start);
+        Span {
+            start,
+            end,
+            line: line + 1,
+            column: start - lines[line] + 1,
+            end_line: line_index(lines, end.max(start + 1) - 1) + 1,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -22,6 +53,28 @@ pub enum DTError {
     Why(String, usize, u32),
 }
 
+impl DTError {
+    pub fn offset(&self) -> usize {
+        match self {
+            DTError::UnexpectedEOF(o) => *o,
+            DTError::UnknownSymbol(_, o) => *o,
+            DTError::BadDefine(o) => *o,
+            DTError::Why(_, o, _) => *o,
+        }
+    }
+}
+
+impl fmt::Display for DTError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DTError::UnexpectedEOF(_) => write!(f, "unexpected end of file"),
+            DTError::UnknownSymbol(s, _) => write!(f, "unknown symbol {:?}", s),
+            DTError::BadDefine(_) => write!(f, "bad #define"),
+            DTError::Why(s, _, l) => write!(f, "unexpected {:?} (lexer line {})", s, l),
+        }
+    }
+}
+
 #[derive(Logos, Debug, PartialEq, Clone, Copy)]
 enum DTToken {
     #[end]
@@ -67,34 +120,43 @@ enum DTToken {
     Text,
 }
 
-pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
-    let mut lexer = DTToken::lexer(file_data.as_str());
-    let mut tokens: Vec<DTInfo> = Vec::new();
+pub fn lex(file_data: &str) -> Result<Vec<(DTInfo, Span)>, DTError> {
+    let mut lexer = DTToken::lexer(file_data);
+    let mut tokens: Vec<(DTInfo, Span)> = Vec::new();
+    let mut lines: Vec<usize> = vec![0];
+    lines.extend(file_data.match_indices('\n').map(|(i, _)| i + 1));
 
     loop {
+        let start = lexer.range().start;
         match lexer.token {
+            // Comments are skipped by position rather than token so that
+            // anything may appear in them; the text is kept as a token.
             DTToken::LineComment => {
-                while lexer.token != DTToken::NewLine {
+                let end = file_data[start..]
+                    .find('\n')
+                    .map_or(file_data.len(), |i| start + i);
+                tokens.push((
+                    DTInfo::Comment(file_data[start..end].to_string()),
+                    Span::new(&lines, start, end),
+                ));
+                while lexer.token != DTToken::End && lexer.range().end <= end {
                     lexer.advance();
-                    if lexer.token == DTToken::End {
-                        return Err(DTError::UnexpectedEOF(lexer.range().start));
-                    } else if lexer.token == DTToken::Error {
-                        return Err(DTError::UnknownSymbol(
-                            lexer.slice().to_string(),
-                            lexer.range().start,
-                        ));
-                    }
+                }
+                if lexer.token == DTToken::End {
+                    continue;
                 }
             }
             DTToken::BlockCommentStart => {
-                while lexer.token != DTToken::BlockCommentEnd {
+                let end = match file_data[start + 2..].find("*/") {
+                    Some(i) => start + 2 + i + 2,
+                    None => return Err(DTError::UnexpectedEOF(start)),
+                };
+                tokens.push((
+                    DTInfo::Comment(file_data[start..end].to_string()),
+                    Span::new(&lines, start, end),
+                ));
+                while lexer.range().end < end {
                     lexer.advance();
-                    if lexer.token == DTToken::End {
-                        return Err(DTError::UnexpectedEOF(lexer.range().start));
-                    } else if lexer.token == DTToken::Error {
-                        let slice = lexer.slice().to_string();
-                        return Err(DTError::UnknownSymbol(slice, lexer.range().start));
-                    }
                 }
             }
             DTToken::Define => {
@@ -112,7 +174,10 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                     }
                     lexer.advance();
                 }
-                tokens.push(DTInfo::Define(lhs, rhs));
+                tokens.push((
+                    DTInfo::Define(lhs, rhs),
+                    Span::new(&lines, start, lexer.range().end),
+                ));
             }
             DTToken::Text => {
                 let lhs = lexer.slice().to_string();
@@ -132,15 +197,26 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                                 DTToken::End => {
                                     return Err(DTError::UnexpectedEOF(lexer.range().start))
                                 }
+                                // Comments inside a value are not kept in the
+                                // token; fmt works from the source text and
+                                // keeps them, the tree and its writers do not.
                                 DTToken::BlockCommentStart => {
-                                    while lexer.token != DTToken::BlockCommentEnd {
+                                    let at = lexer.range().start;
+                                    let end = match file_data[at + 2..].find("*/") {
+                                        Some(i) => at + 2 + i + 2,
+                                        None => return Err(DTError::UnexpectedEOF(at)),
+                                    };
+                                    while lexer.range().end < end {
                                         lexer.advance();
                                     }
                                 }
                                 DTToken::LineComment => {
-                                    while lexer.token != DTToken::NewLine {
+                                    while lexer.token != DTToken::NewLine
+                                        && lexer.token != DTToken::End
+                                    {
                                         lexer.advance();
                                     }
+                                    continue;
                                 }
                                 DTToken::RefNode => {
                                     lexer.advance();
@@ -154,9 +230,15 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                             }
                             lexer.advance();
                         }
-                        tokens.push(DTInfo::Property(lhs, Some(rhs.join(" "))));
+                        tokens.push((
+                            DTInfo::Property(lhs, Some(rhs.join(" "))),
+                            Span::new(&lines, start, lexer.range().end),
+                        ));
                     }
-                    DTToken::StatementEnd => tokens.push(DTInfo::Property(lhs, None)),
+                    DTToken::StatementEnd => tokens.push((
+                        DTInfo::Property(lhs, None),
+                        Span::new(&lines, start, lexer.range().end),
+                    )),
                     DTToken::Label => {
                         let label = lhs;
                         let mut name = String::new();
@@ -167,8 +249,18 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                                 DTToken::Text => {
                                     name = slice;
                                 }
+                                // Kept ahead of the node, as if written before the label.
                                 DTToken::BlockCommentStart => {
-                                    while lexer.token != DTToken::BlockCommentEnd {
+                                    let at = lexer.range().start;
+                                    let end = match file_data[at + 2..].find("*/") {
+                                        Some(i) => at + 2 + i + 2,
+                                        None => return Err(DTError::UnexpectedEOF(at)),
+                                    };
+                                    tokens.push((
+                                        DTInfo::Comment(file_data[at..end].to_string()),
+                                        Span::new(&lines, at, end),
+                                    ));
+                                    while lexer.range().end < end {
                                         lexer.advance();
                                     }
                                 }
@@ -176,9 +268,15 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                             }
                             lexer.advance();
                         }
-                        tokens.push(DTInfo::Node(Some(label), name));
+                        tokens.push((
+                            DTInfo::Node(Some(label), name),
+                            Span::new(&lines, start, lexer.range().end),
+                        ));
                     }
-                    DTToken::NodeStart => tokens.push(DTInfo::Node(None, lhs)),
+                    DTToken::NodeStart => tokens.push((
+                        DTInfo::Node(None, lhs),
+                        Span::new(&lines, start, lexer.range().end),
+                    )),
                     _ => {
                         return Err(DTError::Why(
                             lexer.slice().to_string(),
@@ -189,7 +287,7 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                 }
             }
             DTToken::End => {
-                tokens.push(DTInfo::EOF);
+                tokens.push((DTInfo::EOF, Span::new(&lines, start, lexer.range().end)));
                 break;
             }
             DTToken::Error => {
@@ -199,28 +297,47 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                 ))
             }
             DTToken::NewLine => (),
-            DTToken::NodeEnd => tokens.push(DTInfo::NodeEnd),
+            DTToken::NodeEnd => {
+                tokens.push((DTInfo::NodeEnd, Span::new(&lines, start, lexer.range().end)))
+            }
             DTToken::Include => {
                 let mut file: Option<String> = None;
+                let mut file_end = 0;
                 lexer.advance();
                 while lexer.token != DTToken::NewLine {
                     let slice = lexer.slice().to_string();
                     match lexer.token {
-                        DTToken::Text => {
-                            if file == None {
-                                file = Some(slice);
-                            } else {
-                                return Err(DTError::Why(slice, lexer.range().start, line!()));
+                        // A path like dt-bindings/gpio/gpio.h lexes as several
+                        // adjacent Text tokens.
+                        DTToken::Text => match file.as_mut() {
+                            None => file = Some(slice),
+                            Some(f) if lexer.range().start == file_end => f.push_str(&slice),
+                            Some(_) => {
+                                return Err(DTError::Why(slice, lexer.range().start, line!()))
                             }
-                        }
+                        },
                         DTToken::Quote => (),
                         DTToken::AngleOpen => (),
                         DTToken::AngleClose => (),
                         _ => return Err(DTError::Why(slice, lexer.range().start, line!())),
                     };
+                    file_end = lexer.range().end;
                     lexer.advance();
                 }
-                tokens.push(DTInfo::Include(file.unwrap()));
+                let file = match file {
+                    Some(f) => f,
+                    None => {
+                        return Err(DTError::Why(
+                            lexer.slice().to_string(),
+                            lexer.range().start,
+                            line!(),
+                        ))
+                    }
+                };
+                tokens.push((
+                    DTInfo::Include(file),
+                    Span::new(&lines, start, lexer.range().end),
+                ));
             }
             DTToken::Directive => {
                 let directive = lexer.slice().to_string();
@@ -246,10 +363,21 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                     }
                     lexer.advance();
                 }
-                if !rhs.is_empty() {
-                    tokens.push(DTInfo::Directive(directive, Some(rhs.join(""))));
+                if directive == "/memreserve/" {
+                    tokens.push((
+                        DTInfo::Directive(directive, Some(rhs.join(" "))),
+                        Span::new(&lines, start, lexer.range().end),
+                    ));
+                } else if !rhs.is_empty() {
+                    tokens.push((
+                        DTInfo::Directive(directive, Some(rhs.join(""))),
+                        Span::new(&lines, start, lexer.range().end),
+                    ));
                 } else {
-                    tokens.push(DTInfo::Directive(directive, None));
+                    tokens.push((
+                        DTInfo::Directive(directive, None),
+                        Span::new(&lines, start, lexer.range().end),
+                    ));
                 }
             }
             DTToken::RefNode => {
@@ -264,7 +392,10 @@ pub fn lex(file_data: &String) -> Result<Vec<DTInfo>, DTError> {
                     let node_name = lexer.slice().to_string();
                     lexer.advance();
                     if lexer.token == DTToken::NodeStart {
-                        tokens.push(DTInfo::RefNode(node_name));
+                        tokens.push((
+                            DTInfo::RefNode(node_name),
+                            Span::new(&lines, start, lexer.range().end),
+                        ));
                     } else {
                         return Err(DTError::Why(
                             lexer.slice().to_string(),
@@ -0,0 +1,665 @@
+use crate::dt_lexer::{lex, DTError, DTInfo, Span};
+use crate::dt_value::parse_u64;
+use crate::root::{Location, Root, RootError};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+pub type Tokens = Rc<Vec<(DTInfo, Span)>>;
+
+pub struct Source {
+    pub file: String,
+    pub text: String,
+    pub tokens: Tokens,
+    // The file each `DTInfo::Include` token resolved to, in order.
+    pub includes: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Read(String, String),
+    Lex(String, DTError),
+    Tree(Location, String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Read(p, e) => write!(f, "could not read {}: {}", p, e),
+            LoadError::Lex(p, e) => write!(f, "{}: offset {}: {}", p, e.offset(), e),
+            LoadError::Tree(l, e) => write!(f, "{}: {}", l, e),
+        }
+    }
+}
+
+// Folds `.` and `..` so that a file reached along two paths is one file.
+fn normalize(path: &Path) -> String {
+    let mut parts: Vec<Component> = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir if matches!(parts.last(), Some(Component::Normal(_))) => {
+                parts.pop();
+            }
+            c => parts.push(c),
+        }
+    }
+    parts.iter().collect::<PathBuf>().display().to_string()
+}
+
+// Like dtc, an include is looked up next to the including file first, then
+// in each include directory and then relative to the working directory.
+pub(crate) fn resolve<F: Fn(&str) -> Result<String, String>>(
+    from: &str,
+    name: &str,
+    read: &F,
+    dirs: &[String],
+) -> Result<(String, String), LoadError> {
+    let beside = Path::new(from)
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty());
+    for dir in beside.into_iter().chain(dirs.iter().map(Path::new)) {
+        let candidate = normalize(&dir.join(name));
+        if let Ok(text) = read(&candidate) {
+            return Ok((candidate, text));
+        }
+    }
+    match read(name) {
+        Ok(text) => Ok((name.to_string(), text)),
+        Err(e) => Err(LoadError::Read(name.to_string(), e)),
+    }
+}
+
+// Token streams of files already lexed, keyed by path and checked against a
+// hash of the content, so an edit re-lexes only the files that changed.
+#[derive(Default)]
+pub struct Cache {
+    files: HashMap<String, (u64, Tokens)>,
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut h = DefaultHasher::new();
+    text.hash(&mut h);
+    h.finish()
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Cache::default()
+    }
+
+    fn tokens(&mut self, file: &str, text: &str) -> Result<Tokens, LoadError> {
+        let hash = content_hash(text);
+        if let Some((h, tokens)) = self.files.get(file) {
+            if *h == hash {
+                return Ok(tokens.clone());
+            }
+        }
+        let tokens = match lex(text) {
+            Ok(t) => Rc::new(t),
+            Err(e) => return Err(LoadError::Lex(file.to_string(), e)),
+        };
+        self.files.insert(file.to_string(), (hash, tokens.clone()));
+        Ok(tokens)
+    }
+
+    fn add_source<F: Fn(&str) -> Result<String, String>>(
+        &mut self,
+        file: String,
+        text: String,
+        read: &F,
+        dirs: &[String],
+        found: &mut Vec<Source>,
+    ) -> Result<(), LoadError> {
+        let tokens = self.tokens(&file, &text)?;
+        let mut includes = Vec::new();
+        let mut pending = Vec::new();
+        for (token, _) in tokens.iter() {
+            if let DTInfo::Include(i) = token {
+                let (name, text) = resolve(&file, i, read, dirs)?;
+                includes.push(name.clone());
+                if !found.iter().any(|s| s.file == name) && !pending.iter().any(|(n, _)| *n == name)
+                {
+                    pending.push((name, text));
+                }
+            }
+        }
+        found.push(Source {
+            file,
+            text,
+            tokens,
+            includes,
+        });
+        for (name, text) in pending {
+            if !found.iter().any(|s| s.file == name) {
+                self.add_source(name, text, read, dirs, found)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Like `sources`, but only files whose content changed since the last
+    // call are lexed again.
+    pub fn sources<F: Fn(&str) -> Result<String, String>>(
+        &mut self,
+        first_file: &str,
+        read: &F,
+        options: &Options,
+    ) -> Result<Vec<Source>, LoadError> {
+        let text = match read(first_file) {
+            Ok(t) => t,
+            Err(e) => return Err(LoadError::Read(first_file.to_string(), e)),
+        };
+        let mut found = Vec::new();
+        let dirs = &options.include_dirs;
+        // Named like the includes, so that coming back to it is seen as a cycle.
+        let name = normalize(Path::new(first_file));
+        self.add_source(name, text, read, dirs, &mut found)?;
+        // Forget files nothing includes any more.
+        self.files.retain(|f, _| found.iter().any(|s| s.file == *f));
+        Ok(found)
+    }
+}
+
+// Reads and lexes a file and everything it includes, through `read` so that
+// callers can supply unsaved editor buffers.
+pub fn sources<F: Fn(&str) -> Result<String, String>>(
+    first_file: &str,
+    read: &F,
+    options: &Options,
+) -> Result<Vec<Source>, LoadError> {
+    Cache::new().sources(first_file, read, options)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // Searched for includes not found next to the including file.
+    pub include_dirs: Vec<String>,
+    // Macros defined before the first file, like cpp's -D.
+    pub defines: Vec<(String, String)>,
+    // Process each file once, as if every file had an include guard.
+    pub include_once: bool,
+}
+
+struct Builder {
+    path: Vec<String>,
+    // The path to go back to as each open block closes; `&label { }` jumps.
+    outer: Vec<Vec<String>>,
+    // Child and property names seen in each open block, to catch duplicates.
+    blocks: Vec<(Vec<String>, Vec<String>)>,
+    // Comments wait for the node or property after them, unless they trail
+    // the previous one on its last line; those left when a block closes
+    // belong to its end.
+    comments: Vec<String>,
+    last: Option<(Vec<String>, Option<String>, usize)>,
+    stack: Vec<String>,
+    // Each file included so far, the file including it and where.
+    included: Vec<(String, String, Location)>,
+    include_once: bool,
+    // Macros from -D not yet redefined by the source.
+    predefined: Vec<String>,
+}
+
+impl Builder {
+    fn file(&mut self, sources: &[Source], name: &str, r: &mut Root) -> Result<(), LoadError> {
+        let source = match sources.iter().find(|s| s.file == name) {
+            Some(s) => s,
+            None => return Err(LoadError::Read(name.to_string(), "not loaded".to_string())),
+        };
+        self.stack.push(name.to_string());
+        let mut includes = source.includes.iter();
+        for (token, span) in source.tokens.iter() {
+            let loc = Location {
+                file: name.to_string(),
+                line: span.line,
+                column: span.column,
+            };
+            let fail = |e: String| Err(LoadError::Tree(loc.clone(), e));
+            match token.clone() {
+                DTInfo::Include(i) => {
+                    self.comments.clear();
+                    self.last = None;
+                    let file = includes.next().cloned().unwrap_or(i);
+                    if let Some(at) = self.stack.iter().position(|f| *f == file) {
+                        let mut chain = self.stack[at..].to_vec();
+                        chain.push(file);
+                        let chain = chain.join(" -> ");
+                        if !self.include_once {
+                            return fail(format!("include cycle: {}", chain));
+                        }
+                        r.add_warning(loc, format!("include cycle skipped: {}", chain));
+                        continue;
+                    }
+                    match self.included.iter().find(|(f, _, _)| *f == file) {
+                        Some((_, from, first)) => {
+                            // Headers are usually guarded and meant to be
+                            // included wherever they are needed.
+                            let source = file.ends_with(".dts") || file.ends_with(".dtsi");
+                            if source || from == name {
+                                let message = format!("{} already included at {}", file, first);
+                                r.add_warning(loc.clone(), message);
+                            }
+                            if self.include_once {
+                                continue;
+                            }
+                        }
+                        None => self.included.push((file.clone(), name.to_string(), loc)),
+                    }
+                    self.file(sources, &file, r)?;
+                    self.last = None;
+                }
+                DTInfo::Directive(d, t) => {
+                    self.comments.clear();
+                    self.last = None;
+                    match d.as_str() {
+                        "/delete-node/" => {
+                            let path = match t {
+                                Some(t) => match t.strip_prefix('&') {
+                                    Some(label) => r.label_path(label).cloned(),
+                                    None => {
+                                        let mut p = self.path.clone();
+                                        p.extend(t.split('/').map(|i| i.to_string()));
+                                        Some(p)
+                                    }
+                                },
+                                None => return fail("Unknown node to delete".to_string()),
+                            };
+                            let deleted = match &path {
+                                Some(p) => r.delete_node(p),
+                                None => Err(RootError::UnknownLabel),
+                            };
+                            if let Err(e) = deleted {
+                                return fail(format!("Error while deleting node: {}", e));
+                            }
+                            // The block may add the node again.
+                            if let (Some(p), Some((children, _))) = (path, self.blocks.last_mut()) {
+                                if p[..p.len() - 1] == self.path[..] {
+                                    children.retain(|c| Some(c) != p.last());
+                                }
+                            }
+                        }
+                        "/delete-property/" => {
+                            let name = t.unwrap_or_default();
+                            if let Err(e) = r.delete_property(&self.path, &name) {
+                                return fail(format!("Error while deleting {}: {}", name, e));
+                            }
+                            if let Some((_, properties)) = self.blocks.last_mut() {
+                                properties.retain(|p| *p != name);
+                            }
+                        }
+                        "/memreserve/" => {
+                            let region: Option<Vec<u64>> = t
+                                .unwrap_or_default()
+                                .split_whitespace()
+                                .map(parse_u64)
+                                .collect();
+                            match region.as_deref() {
+                                Some([address, size]) => r.add_memreserve(*address, *size, loc),
+                                _ => return fail("Bad /memreserve/".to_string()),
+                            }
+                        }
+                        "/dts-v1/" => (),
+                        _ => eprintln!("directive: {}", d),
+                    };
+                }
+                DTInfo::Node(label, name) => {
+                    if let Err(e) = r.add_node(&self.path, &name) {
+                        return fail(format!("Error when adding node: {}", e));
+                    }
+                    self.outer.push(self.path.clone());
+                    self.path.push(name.clone());
+                    if let Some((children, _)) = self.blocks.last_mut() {
+                        if children.contains(&name) {
+                            r.add_duplicate(&self.path, None, loc.clone());
+                        } else {
+                            children.push(name);
+                        }
+                    }
+                    self.blocks.push((Vec::new(), Vec::new()));
+                    r.add_location(&self.path, None, loc);
+                    for c in self.comments.drain(..) {
+                        r.add_comment(&self.path, None, c);
+                    }
+                    self.last = Some((self.path.clone(), None, span.end_line));
+                    if let Some(l) = label {
+                        r.add_path(l, &self.path);
+                    }
+                }
+                DTInfo::NodeEnd => {
+                    for c in self.comments.drain(..) {
+                        r.add_end_comment(&self.path, c);
+                    }
+                    self.last = Some((self.path.clone(), None, span.end_line));
+                    self.path = self.outer.pop().unwrap_or_default();
+                    self.blocks.pop();
+                }
+                DTInfo::Property(p, v) => {
+                    if let Err(e) = r.add_property(&self.path, (p.clone(), v)) {
+                        return fail(format!("Error when adding property: {}", e));
+                    }
+                    if let Some((_, properties)) = self.blocks.last_mut() {
+                        if properties.contains(&p) {
+                            r.add_duplicate(&self.path, Some(p.clone()), loc.clone());
+                        } else {
+                            properties.push(p.clone());
+                        }
+                    }
+                    r.add_location(&self.path, Some(p.clone()), loc);
+                    for c in self.comments.drain(..) {
+                        r.add_comment(&self.path, Some(p.clone()), c);
+                    }
+                    self.last = Some((self.path.clone(), Some(p), span.end_line));
+                }
+                DTInfo::Define(n, v) => {
+                    self.comments.clear();
+                    self.last = None;
+                    // Like cpp, an identical redefinition is no error, and
+                    // the source may redefine a -D with a warning.
+                    if r.define(&n) == Some(&v) {
+                        continue;
+                    }
+                    if let Some(at) = self.predefined.iter().position(|d| *d == n) {
+                        self.predefined.remove(at);
+                        if let Some(old) = r.undefine(&n) {
+                            if old.trim() != v.trim() {
+                                let message = format!("{} redefined, was -D {}={}", n, n, old);
+                                r.add_warning(loc.clone(), message);
+                            }
+                        }
+                    }
+                    if let Err(e) = r.add_define(n, v) {
+                        return fail(format!("Error when adding define: {}", e));
+                    }
+                }
+                DTInfo::EOF => {
+                    self.comments.clear();
+                    self.last = None;
+                    break;
+                }
+                DTInfo::Comment(c) => match &self.last {
+                    Some((p, property, line)) if *line == span.line => {
+                        r.add_comment(p, property.clone(), c)
+                    }
+                    _ => self.comments.push(c),
+                },
+                DTInfo::RefNode(n) => {
+                    match r.label_path(&n) {
+                        Some(p) => self.outer.push(std::mem::replace(&mut self.path, p.clone())),
+                        None => return fail(format!("unknown label {}", n)),
+                    };
+                    self.blocks.push((Vec::new(), Vec::new()));
+                    r.add_location(&self.path, None, loc);
+                    for c in self.comments.drain(..) {
+                        r.add_comment(&self.path, None, c);
+                    }
+                    self.last = Some((self.path.clone(), None, span.end_line));
+                }
+            }
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+// Builds the tree from lexed sources, starting at `first_file` and splicing
+// each include in where it appears.
+pub fn build_with(
+    sources: &[Source],
+    first_file: &str,
+    options: &Options,
+) -> Result<Root, LoadError> {
+    let mut b = Builder {
+        path: Vec::new(),
+        outer: Vec::new(),
+        blocks: Vec::new(),
+        comments: Vec::new(),
+        last: None,
+        stack: Vec::new(),
+        included: Vec::new(),
+        include_once: options.include_once,
+        predefined: options.defines.iter().map(|(n, _)| n.clone()).collect(),
+    };
+    let mut r = Root::new();
+    for (name, value) in &options.defines {
+        if let Err(e) = r.add_define(name.clone(), value.clone()) {
+            let loc = Location {
+                file: first_file.to_string(),
+                line: 1,
+                column: 1,
+            };
+            return Err(LoadError::Tree(loc, format!("-D {}: {}", name, e)));
+        }
+    }
+    b.file(sources, &normalize(Path::new(first_file)), &mut r)?;
+    Ok(r)
+}
+
+pub fn build(sources: &[Source], first_file: &str) -> Result<Root, LoadError> {
+    build_with(sources, first_file, &Options::default())
+}
+
+pub fn load<F: Fn(&str) -> Result<String, String>>(
+    first_file: &str,
+    read: &F,
+    options: &Options,
+) -> Result<Root, LoadError> {
+    build_with(&sources(first_file, read, options)?, first_file, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, LoadError, Options};
+    use crate::root::Root;
+    use std::collections::HashMap;
+
+    fn load_files(files: &[(&str, &str)], options: &Options) -> Result<Root, LoadError> {
+        let first = files[0].0;
+        let files: HashMap<String, String> = files
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        let read = |f: &str| files.get(f).cloned().ok_or_else(|| "missing".to_string());
+        load(first, &read, options)
+    }
+
+    #[test]
+    fn memreserve_above_4gib() {
+        let dts = "/dts-v1/;\n/memreserve/ 0x100000000 0x1000;\n/ {\n};\n";
+        let r = load_files(&[("a.dts", dts)], &Options::default()).unwrap();
+        let reserved: Vec<(u64, u64)> = r.memreserve().iter().map(|m| (m.0, m.1)).collect();
+        assert_eq!(reserved, vec![(0x1_0000_0000, 0x1000)]);
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let files = [
+            ("a.dts", "/dts-v1/;\n#include \"b.dtsi\"\n/ {\n};\n"),
+            ("b.dtsi", "#include \"a.dts\"\n"),
+        ];
+        match load_files(&files, &Options::default()) {
+            Err(LoadError::Tree(_, e)) => assert_eq!(e, "include cycle: a.dts -> b.dtsi -> a.dts"),
+            _ => panic!("expected an include cycle"),
+        }
+    }
+
+    #[test]
+    fn include_cycle_through_dot_path() {
+        let files: HashMap<&str, &str> = [
+            ("a.dts", "/dts-v1/;\n#include \"b.dtsi\"\n/ {\n};\n"),
+            ("b.dtsi", "#include \"a.dts\"\n"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let read = |f: &str| match files.get(f.trim_start_matches("./")) {
+            Some(t) => Ok(t.to_string()),
+            None => Err("missing".to_string()),
+        };
+        match load("./a.dts", &read, &Options::default()) {
+            Err(LoadError::Tree(_, e)) => assert_eq!(e, "include cycle: a.dts -> b.dtsi -> a.dts"),
+            _ => panic!("expected an include cycle"),
+        }
+    }
+
+    #[test]
+    fn repeated_include_warns() {
+        let files = [
+            (
+                "a.dts",
+                "/dts-v1/;\n#include \"b.dtsi\"\n#include \"b.dtsi\"\n",
+            ),
+            ("b.dtsi", "/ {\n\tx;\n};\n"),
+        ];
+        let r = load_files(&files, &Options::default()).unwrap();
+        let warnings: Vec<&String> = r.warnings().iter().map(|(_, w)| w).collect();
+        assert_eq!(warnings, vec!["b.dtsi already included at a.dts:2:1"]);
+    }
+
+    #[test]
+    fn include_once_skips_cycles_and_repeats() {
+        let files = [
+            (
+                "a.dts",
+                "/dts-v1/;\n#include \"b.dtsi\"\n#include \"b.dtsi\"\n",
+            ),
+            ("b.dtsi", "#include \"a.dts\"\n#define X 1\n/ {\n\tx;\n};\n"),
+        ];
+        let options = Options {
+            include_once: true,
+            ..Options::default()
+        };
+        let r = load_files(&files, &options).unwrap();
+        assert!(r.get_node(&["/"]).unwrap().has_property("x"));
+        assert_eq!(r.warnings().len(), 2);
+    }
+
+    #[test]
+    fn identical_redefinition_is_allowed() {
+        let dts = "/dts-v1/;\n#define X 1\n#define X 1\n/ {\n};\n";
+        assert!(load_files(&[("a.dts", dts)], &Options::default()).is_ok());
+        let dts = "/dts-v1/;\n#define X 1\n#define X 2\n/ {\n};\n";
+        assert!(load_files(&[("a.dts", dts)], &Options::default()).is_err());
+    }
+
+    #[test]
+    fn source_redefines_command_line_macro() {
+        let dts = "/dts-v1/;\n#define REV 3\n/ {\n\trev = <REV>;\n};\n";
+        let options = Options {
+            defines: vec![("REV".to_string(), "2".to_string())],
+            ..Options::default()
+        };
+        let r = load_files(&[("a.dts", dts)], &options).unwrap();
+        let node = r.get_node(&["/"]).unwrap();
+        assert_eq!(r.u32s(node, "rev"), Some(vec![3]));
+        let warnings: Vec<&String> = r.warnings().iter().map(|(_, w)| w).collect();
+        assert_eq!(warnings, vec!["REV redefined, was -D REV=2"]);
+    }
+
+    #[test]
+    fn deleted_node_may_be_added_again() {
+        let dts = "/dts-v1/;\n/ {\n\ta { };\n\t/delete-node/ a;\n\ta { };\n\tx;\n\t/delete-property/ x;\n\tx;\n};\n";
+        let r = load_files(&[("a.dts", dts)], &Options::default()).unwrap();
+        assert!(r.duplicates().is_empty());
+    }
+
+    #[test]
+    fn trailing_comment_belongs_to_block_end() {
+        let dts = "/dts-v1/;\n/ {\n\t/* uart */\n\tuart {\n\t\tx;\n\t\t/* end */\n\t};\n};\n";
+        let r = load_files(&[("a.dts", dts)], &Options::default()).unwrap();
+        let uart = r.get_node(&["/", "uart"]).unwrap();
+        assert_eq!(uart.comments(), &vec!["/* uart */".to_string()]);
+        assert_eq!(uart.end_comments(), &vec!["/* end */".to_string()]);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use super::{build, sources, Cache, Options};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use test::Bencher;
+
+    const BUSES: usize = 64;
+    const DEVICES: usize = 100;
+
+    // A SoC-sized tree: a board file including one .dtsi per bus, each adding
+    // a bus of devices to the board's /soc.
+    fn generate() -> HashMap<String, String> {
+        let mut files = HashMap::new();
+        let mut board = String::from("/dts-v1/;\n\n/ {\n\tmodel = \"bench\";\n");
+        board.push_str("\tsoc: soc {\n\t\t#address-cells = <1>;\n\t\t#size-cells = <1>;\n");
+        board.push_str("\t\tranges;\n\t};\n};\n\n");
+        for b in 0..BUSES {
+            board.push_str(&format!("#include \"bus{}.dtsi\"\n", b));
+            let mut bus = format!(
+                "&soc {{\n\tbus@{:x} {{\n\t\tcompatible = \"simple-bus\";\n",
+                b << 20
+            );
+            bus.push_str("\t\t#address-cells = <1>;\n\t\t#size-cells = <1>;\n\t\tranges;\n");
+            for d in 0..DEVICES {
+                let address = (b << 20) | (d << 12);
+                bus.push_str(&format!(
+                    "\n\t\tdev{}_{}: device@{:x} {{\n\t\t\tcompatible = \"vendor,device\";\n\
+                     \t\t\treg = <{:#x} 0x100>;\n\t\t\tinterrupts = <0 {} 4>;\n\
+                     \t\t\tstatus = \"okay\";\n\t\t}};\n",
+                    b, d, address, address, d
+                ));
+            }
+            bus.push_str("\t};\n};\n");
+            files.insert(format!("bus{}.dtsi", b), bus);
+        }
+        files.insert("board.dts".to_string(), board);
+        files
+    }
+
+    // Each iteration sees an edit to one include, as an editor would.
+    fn editing(
+        files: &HashMap<String, String>,
+        edited: &Cell<bool>,
+        f: &str,
+    ) -> Result<String, String> {
+        let text = files
+            .get(f)
+            .cloned()
+            .ok_or_else(|| "no such file".to_string())?;
+        if f == "bus0.dtsi" && edited.get() {
+            Ok(text.replacen("\"okay\"", "\"disabled\"", 1))
+        } else {
+            Ok(text)
+        }
+    }
+
+    #[bench]
+    fn reload_all(b: &mut Bencher) {
+        let files = generate();
+        let edited = Cell::new(false);
+        let read = |f: &str| editing(&files, &edited, f);
+        let options = Options::default();
+        b.iter(|| {
+            edited.set(!edited.get());
+            build(&sources("board.dts", &read, &options).unwrap(), "board.dts").unwrap()
+        });
+    }
+
+    #[bench]
+    fn reload_cached(b: &mut Bencher) {
+        let files = generate();
+        let edited = Cell::new(false);
+        let read = |f: &str| editing(&files, &edited, f);
+        let options = Options::default();
+        let mut cache = Cache::new();
+        b.iter(|| {
+            edited.set(!edited.get());
+            build(
+                &cache.sources("board.dts", &read, &options).unwrap(),
+                "board.dts",
+            )
+            .unwrap()
+        });
+    }
+}
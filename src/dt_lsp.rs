@@ -0,0 +1,662 @@
+extern crate serde_json;
+
+use crate::dt_check::{self, Level};
+use crate::dt_lexer::DTInfo;
+use crate::dt_load::{self, LoadError, Source};
+use crate::dt_schema::{self, Schema};
+use crate::dt_style;
+use crate::dt_value::render;
+use crate::root::{path_string, Location, Root};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+struct Analysis {
+    sources: Vec<Source>,
+    root: Option<Root>,
+    error: Option<LoadError>,
+}
+
+struct Occurrence {
+    file: String,
+    start: usize,
+    end: usize,
+    definition: bool,
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    // One cache per document analysed, as each has its own set of includes.
+    caches: RefCell<HashMap<String, dt_load::Cache>>,
+    schemas: Vec<Schema>,
+    options: dt_load::Options,
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(l) = line.strip_prefix("Content-Length:") {
+            length = l.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    Some(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn send<W: Write>(out: &mut W, message: &Value) {
+    let body = message.to_string();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = out.flush();
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let raw = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+        let hex = raw
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (raw[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                bytes.push(b);
+                i += 3;
+            }
+            (c, _) => {
+                bytes.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let absolute = match env::current_dir() {
+        Ok(d) if Path::new(path).is_relative() => d.join(path).display().to_string(),
+        _ => path.to_string(),
+    };
+    format!(
+        "file://{}",
+        absolute.replace('%', "%25").replace(' ', "%20")
+    )
+}
+
+// LSP positions count lines from 0 and UTF-16 code units within the line;
+// offsets here are bytes.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let start: usize = text.split('\n').take(line).map(|l| l.len() + 1).sum();
+    let start = start.min(text.len());
+    let length = text[start..].find('\n').unwrap_or(text.len() - start);
+    let mut units = 0;
+    for (i, c) in text[start..start + length].char_indices() {
+        if units >= character {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    start + length
+}
+
+fn position(text: &str, offset: usize) -> Value {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({"start": position(text, start), "end": position(text, end)})
+}
+
+// Locations are 1-based lines and byte columns.
+fn location_range(text: &str, l: &Location) -> Value {
+    let start: usize = text
+        .split('\n')
+        .take(l.line.saturating_sub(1))
+        .map(|l| l.len() + 1)
+        .sum();
+    let o = start + l.column.saturating_sub(1);
+    range(text, o, o)
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The identifier around `at` and the character before it.
+fn word_at(text: &str, at: usize) -> Option<(usize, usize, Option<char>)> {
+    let start = text[..at]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_ident(*c))
+        .last()
+        .map_or(at, |(i, _)| i);
+    let end = at
+        + text[at..]
+            .find(|c: char| !is_ident(c))
+            .unwrap_or(text.len() - at);
+    if start == end {
+        return None;
+    }
+    Some((start, end, text[..start].chars().last()))
+}
+
+fn occurrences(sources: &[Source], label: &str) -> Vec<Occurrence> {
+    let mut found = Vec::new();
+    let reference = format!("&{}", label);
+    for s in sources {
+        for (token, span) in s.tokens.iter() {
+            let at = |start: usize, definition| Occurrence {
+                file: s.file.clone(),
+                start,
+                end: start + label.len(),
+                definition,
+            };
+            match token {
+                DTInfo::Node(Some(l), _) if l == label => found.push(at(span.start, true)),
+                DTInfo::RefNode(l) if l == label => found.push(at(span.start + 1, false)),
+                DTInfo::Property(..) | DTInfo::Directive(..) => {
+                    let source = &s.text[span.start..span.end];
+                    for (i, _) in source.match_indices(&reference) {
+                        let after = source[i + reference.len()..].chars().next();
+                        if !after.map_or(false, is_ident) {
+                            found.push(at(span.start + i + 1, false));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    found
+}
+
+fn node_hover(root: &Root, path: &[String]) -> String {
+    let node = match root.get_node(path) {
+        Some(n) => n,
+        None => return String::new(),
+    };
+    let mut s = format!("**{}**", path_string(path));
+    let labels = root.labels_for(path);
+    if !labels.is_empty() {
+        s.push_str(&format!(" ({})", labels.join(", ")));
+    }
+    s.push_str("\n\n");
+    for c in node.comments() {
+        s.push_str(&format!("```dts\n{}\n```\n", c));
+    }
+    s.push_str("```dts\n");
+    for (name, value) in node.properties() {
+        match value {
+            Some(v) => s.push_str(&format!("{} = {};\n", name, render(v))),
+            None => s.push_str(&format!("{};\n", name)),
+        }
+    }
+    for c in node.children() {
+        s.push_str(&format!("{} {{ ... }};\n", c.name));
+    }
+    s.push_str("```\n");
+    for l in node.locations() {
+        s.push_str(&format!("\n{}", l));
+    }
+    s
+}
+
+fn property_hover(root: &Root, path: &[String], name: &str) -> String {
+    let node = match root.get_node(path) {
+        Some(n) => n,
+        None => return String::new(),
+    };
+    let mut s = format!("**{}**\n\n```dts\n", path_string(path));
+    for c in node.property_comments(name) {
+        s.push_str(&format!("{}\n", c));
+    }
+    match node.property(name) {
+        Some(Some(v)) => s.push_str(&format!("{} = {};\n", name, render(v))),
+        _ => s.push_str(&format!("{};\n", name)),
+    }
+    s.push_str("```");
+    if let Some(l) = node.property_location(name) {
+        s.push_str(&format!("\n\n{}", l));
+    }
+    s
+}
+
+fn macro_hover(root: &Root, name: &str) -> Option<String> {
+    let (_, value) = root
+        .defines()
+        .into_iter()
+        .find(|(n, _)| n.as_str() == name)?;
+    let mut s = format!("```c\n#define {} {}\n```", name, value.trim());
+    if let Some(n) = root.expand(name) {
+        s.push_str(&format!("\n\nexpands to {} ({:#x})", n, n));
+    }
+    Some(s)
+}
+
+fn symbols(source: &Source) -> Vec<Value> {
+    let mut stack: Vec<(Value, Vec<Value>)> = Vec::new();
+    let mut top = Vec::new();
+    for (token, span) in source.tokens.iter() {
+        let r = range(&source.text, span.start, span.end);
+        match token {
+            DTInfo::Node(label, name) => {
+                let detail = label.clone().unwrap_or_default();
+                stack.push((
+                    json!({"name": name, "detail": detail, "kind": 19, "range": r,
+                           "selectionRange": r}),
+                    Vec::new(),
+                ));
+            }
+            DTInfo::RefNode(label) => {
+                stack.push((
+                    json!({"name": format!("&{}", label), "kind": 19, "range": r,
+                           "selectionRange": r}),
+                    Vec::new(),
+                ));
+            }
+            DTInfo::NodeEnd => {
+                if let Some((mut symbol, children)) = stack.pop() {
+                    let start = symbol["range"]["start"].clone();
+                    symbol["range"] = json!({"start": start, "end": r["end"]});
+                    symbol["children"] = Value::Array(children);
+                    match stack.last_mut() {
+                        Some((_, c)) => c.push(symbol),
+                        None => top.push(symbol),
+                    }
+                }
+            }
+            DTInfo::Property(name, _) => {
+                let symbol = json!({"name": name, "kind": 7, "range": r, "selectionRange": r});
+                match stack.last_mut() {
+                    Some((_, c)) => c.push(symbol),
+                    None => top.push(symbol),
+                }
+            }
+            DTInfo::Define(name, _) => {
+                top.push(json!({"name": name, "kind": 14, "range": r, "selectionRange": r}))
+            }
+            _ => (),
+        }
+    }
+    top
+}
+
+fn diagnostic(range: Value, severity: u32, source: &str, message: String) -> Value {
+    json!({"range": range, "severity": severity, "source": source, "message": message})
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    fn analyze(&self, path: &str) -> Analysis {
+        let read = |f: &str| match self.documents.get(f) {
+            Some(t) => Ok(t.clone()),
+            None => fs::read_to_string(f).map_err(|e| e.to_string()),
+        };
+        let mut caches = self.caches.borrow_mut();
+        let cache = caches.entry(path.to_string()).or_default();
+        let sources = match cache.sources(path, &read, &self.options) {
+            Ok(s) => s,
+            Err(e) => {
+                return Analysis {
+                    sources: Vec::new(),
+                    root: None,
+                    error: Some(e),
+                }
+            }
+        };
+        let (root, error) = match dt_load::build_with(&sources, path, &self.options) {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e)),
+        };
+        Analysis {
+            sources,
+            root,
+            error,
+        }
+    }
+
+    fn text(&self, path: &str) -> String {
+        match self.documents.get(path) {
+            Some(t) => t.clone(),
+            None => fs::read_to_string(path).unwrap_or_default(),
+        }
+    }
+
+    fn diagnostics(&self, path: &str) -> Vec<Value> {
+        let text = self.text(path);
+        let a = self.analyze(path);
+        let mut found = Vec::new();
+        let here = |l: &Location| l.file == path;
+        match &a.error {
+            Some(LoadError::Lex(f, e)) if f == path => {
+                let o = e.offset().min(text.len());
+                found.push(diagnostic(range(&text, o, o), 1, "mimir", e.to_string()));
+            }
+            Some(LoadError::Tree(l, e)) if here(l) => {
+                found.push(diagnostic(location_range(&text, l), 1, "mimir", e.clone()))
+            }
+            Some(e) => found.push(diagnostic(range(&text, 0, 0), 1, "mimir", e.to_string())),
+            None => (),
+        }
+        for (l, w) in a.root.iter().flat_map(|r| r.warnings()) {
+            if here(l) {
+                found.push(diagnostic(location_range(&text, l), 2, "mimir", w.clone()));
+            }
+        }
+        if let Some(s) = a.sources.iter().find(|s| s.file == path) {
+            for f in dt_style::lint_tokens(path, &s.text, &s.tokens) {
+                found.push(diagnostic(
+                    location_range(&text, &f.location),
+                    3,
+                    "mimir-lint",
+                    format!("{} [{}]", f.message, f.rule),
+                ));
+            }
+        }
+        if let Some(root) = &a.root {
+            let config = dt_check::Config::new(&[], &[]);
+            for f in dt_check::run(root, &config) {
+                if let Some(l) = f.location.as_ref().filter(|l| here(l)) {
+                    let severity = if f.level == Level::Error { 1 } else { 2 };
+                    found.push(diagnostic(
+                        location_range(&text, l),
+                        severity,
+                        "mimir-checks",
+                        format!("{} [{}]", f.message, f.check),
+                    ));
+                }
+            }
+            for v in dt_schema::validate(root, &self.schemas) {
+                if let Some(l) = v.location.as_ref().filter(|l| here(l)) {
+                    found.push(diagnostic(
+                        location_range(&text, l),
+                        1,
+                        "mimir-schema",
+                        format!("{}: {}", path_string(&v.path), v.message),
+                    ));
+                }
+            }
+        }
+        found
+    }
+
+    fn publish<W: Write>(&self, out: &mut W, path: &str, diagnostics: Vec<Value>) {
+        send(
+            out,
+            &json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": path_to_uri(path), "diagnostics": diagnostics}}),
+        );
+    }
+
+    fn definition(&self, path: &str, at: &Value) -> Value {
+        let text = self.text(path);
+        let (start, end, _) = match word_at(&text, offset(&text, at)) {
+            Some(w) => w,
+            None => return Value::Null,
+        };
+        let word = &text[start..end];
+        let a = self.analyze(path);
+        let found: Vec<Value> = occurrences(&a.sources, word)
+            .into_iter()
+            .filter(|o| o.definition)
+            .map(|o| {
+                let t = self.text(&o.file);
+                json!({"uri": path_to_uri(&o.file), "range": range(&t, o.start, o.end)})
+            })
+            .collect();
+        if !found.is_empty() {
+            return Value::Array(found);
+        }
+        // Fall back to a macro's #define.
+        for s in &a.sources {
+            for (token, span) in s.tokens.iter() {
+                if let DTInfo::Define(n, _) = token {
+                    if n == word {
+                        return json!([{"uri": path_to_uri(&s.file),
+                                       "range": range(&s.text, span.start, span.start)}]);
+                    }
+                }
+            }
+        }
+        Value::Null
+    }
+
+    fn references(&self, path: &str, at: &Value, declaration: bool) -> Value {
+        let text = self.text(path);
+        let (start, end, _) = match word_at(&text, offset(&text, at)) {
+            Some(w) => w,
+            None => return Value::Null,
+        };
+        let a = self.analyze(path);
+        let found: Vec<Value> = occurrences(&a.sources, &text[start..end])
+            .into_iter()
+            .filter(|o| declaration || !o.definition)
+            .map(|o| {
+                let t = self.text(&o.file);
+                json!({"uri": path_to_uri(&o.file), "range": range(&t, o.start, o.end)})
+            })
+            .collect();
+        Value::Array(found)
+    }
+
+    fn hover(&self, path: &str, at: &Value) -> Value {
+        let text = self.text(path);
+        let o = offset(&text, at);
+        let a = self.analyze(path);
+        let root = match &a.root {
+            Some(r) => r,
+            None => return Value::Null,
+        };
+        let line = text[..o].matches('\n').count() + 1;
+        let mut contents = None;
+        if let Some((start, end, before)) = word_at(&text, o) {
+            let word = &text[start..end];
+            let after = text[end..].chars().next();
+            contents = macro_hover(root, word);
+            if contents.is_none() && (before == Some('&') || after == Some(':')) {
+                contents = root
+                    .labels()
+                    .into_iter()
+                    .find(|(l, _)| l.as_str() == word)
+                    .map(|(_, p)| node_hover(root, p));
+            }
+        }
+        if contents.is_none() {
+            let here = |l: &Location| l.file == path && l.line == line;
+            for (p, node) in root.nodes() {
+                if node.locations().iter().any(&here) {
+                    contents = Some(node_hover(root, &p));
+                }
+                for (name, _) in node.properties() {
+                    if node.property_location(name).map_or(false, &here) {
+                        contents = Some(property_hover(root, &p, name));
+                    }
+                }
+            }
+        }
+        match contents {
+            Some(c) => json!({"contents": {"kind": "markdown", "value": c}}),
+            None => Value::Null,
+        }
+    }
+
+    fn completion(&self, path: &str, at: &Value) -> Value {
+        let text = self.text(path);
+        let o = offset(&text, at);
+        let before = match word_at(&text, o) {
+            Some((start, _, before)) if start < o => before,
+            _ => text[..o].chars().last(),
+        };
+        let a = self.analyze(path);
+        let root = match &a.root {
+            Some(r) => r,
+            None => return json!([]),
+        };
+        let items: Vec<Value> = if before == Some('&') {
+            root.labels()
+                .into_iter()
+                .map(|(l, p)| json!({"label": l, "kind": 18, "detail": path_string(p)}))
+                .collect()
+        } else {
+            root.defines()
+                .into_iter()
+                .map(|(n, v)| json!({"label": n, "kind": 21, "detail": v.trim()}))
+                .collect()
+        };
+        Value::Array(items)
+    }
+
+    fn initialize(&mut self, params: &Value) -> Value {
+        let dirs = params["initializationOptions"]["includeDirs"].as_array();
+        for d in dirs.into_iter().flatten().filter_map(|d| d.as_str()) {
+            self.options.include_dirs.push(d.to_string());
+        }
+        if let Some(dirs) = params["initializationOptions"]["schemas"].as_array() {
+            for d in dirs.iter().filter_map(|d| d.as_str()) {
+                match dt_schema::load_dir(Path::new(d)) {
+                    Ok(mut s) => self.schemas.append(&mut s),
+                    Err(e) => eprintln!("error loading schemas from {}: {}", d, e),
+                }
+            }
+        }
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "hoverProvider": true,
+                "documentSymbolProvider": true,
+                "completionProvider": {"triggerCharacters": ["&"]}
+            },
+            "serverInfo": {"name": "mimir"}
+        })
+    }
+
+    // Handles one message; false once the client has asked the server to exit.
+    pub fn handle<W: Write>(&mut self, message: &Value, out: &mut W) -> bool {
+        let params = &message["params"];
+        let path = params["textDocument"]["uri"].as_str().map(uri_to_path);
+        let path = path.as_deref().unwrap_or("");
+        let at = &params["position"];
+        let result = match message["method"].as_str().unwrap_or("") {
+            "initialize" => Some(self.initialize(params)),
+            "shutdown" => Some(Value::Null),
+            "exit" => return false,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(path.to_string(), text.to_string());
+                self.publish(out, path, self.diagnostics(path));
+                None
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(path.to_string(), text.to_string());
+                }
+                self.publish(out, path, self.diagnostics(path));
+                None
+            }
+            "textDocument/didSave" => {
+                self.publish(out, path, self.diagnostics(path));
+                None
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(path);
+                self.caches.borrow_mut().remove(path);
+                self.publish(out, path, Vec::new());
+                None
+            }
+            "textDocument/definition" => Some(self.definition(path, at)),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"].as_bool();
+                Some(self.references(path, at, declaration.unwrap_or(true)))
+            }
+            "textDocument/hover" => Some(self.hover(path, at)),
+            "textDocument/documentSymbol" => {
+                let a = self.analyze(path);
+                let found = a.sources.iter().find(|s| s.file == path).map(symbols);
+                Some(Value::Array(found.unwrap_or_default()))
+            }
+            "textDocument/completion" => Some(self.completion(path, at)),
+            _ => {
+                if !message["id"].is_null() {
+                    send(
+                        out,
+                        &json!({"jsonrpc": "2.0", "id": message["id"],
+                                "error": {"code": -32601, "message": "method not found"}}),
+                    );
+                }
+                None
+            }
+        };
+        if let Some(r) = result {
+            send(
+                out,
+                &json!({"jsonrpc": "2.0", "id": message["id"], "result": r}),
+            );
+        }
+        true
+    }
+}
+
+pub fn run() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input) {
+        if !server.handle(&message, &mut out) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::location_range;
+    use crate::root::Location;
+    use serde_json::json;
+
+    #[test]
+    fn ranges_count_utf16_units() {
+        // "é" is two bytes but one UTF-16 unit, "𝄞" four bytes and two units.
+        let text = "/ {\n\tmodel = \"é𝄞\"; x;\n};\n";
+        let l = Location {
+            file: "a.dts".to_string(),
+            line: 2,
+            column: 20,
+        };
+        let p = json!({"line": 1, "character": 16});
+        assert_eq!(location_range(text, &l), json!({"start": p, "end": p}));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::dt_address::{windows, Region};
+use crate::dt_value::strings;
+use crate::root::{path_string, Location, Root};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Device,
+    Memory,
+    Reserved,
+    MemReserve,
+}
+
+#[derive(Clone, Debug)]
+pub struct Claim {
+    pub kind: Kind,
+    pub path: Vec<String>,
+    pub region: Region,
+    pub location: Option<Location>,
+}
+
+impl Claim {
+    fn describe(&self) -> String {
+        let what = match self.kind {
+            Kind::MemReserve => "/memreserve/".to_string(),
+            _ => path_string(&self.path),
+        };
+        let at = match &self.location {
+            Some(l) => format!(" ({})", l),
+            None => String::new(),
+        };
+        format!(
+            "{} [{:#x}-{:#x}]{}",
+            what,
+            self.region.address,
+            self.region.address.saturating_add(self.region.size.saturating_sub(1)),
+            at
+        )
+    }
+}
+
+fn kind(root: &Root, path: &[String]) -> Kind {
+    let is_memory = match root.get_node(path).and_then(|n| n.property("device_type")) {
+        Some(t) => strings(t).iter().any(|t| t == "memory"),
+        None => false,
+    };
+    if is_memory {
+        Kind::Memory
+    } else if path.len() > 2 && path[1] == "reserved-memory" {
+        Kind::Reserved
+    } else {
+        Kind::Device
+    }
+}
+
+pub fn claims(root: &Root) -> Vec<Claim> {
+    let mut found = Vec::new();
+    for w in windows(root) {
+        let cpu = match w.cpu {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+        if w.local.size == 0 || !root.is_enabled(&w.path) {
+            continue;
+        }
+        let node = root.get_node(&w.path);
+        found.push(Claim {
+            kind: kind(root, &w.path),
+            location: node.and_then(|n| {
+                n.property_location("reg")
+                    .or_else(|| n.locations().first())
+                    .cloned()
+            }),
+            path: w.path,
+            region: Region {
+                address: cpu,
+                size: w.local.size,
+            },
+        });
+    }
+    for (address, size, loc) in root.memreserve() {
+        if *size > 0 {
+            found.push(Claim {
+                kind: Kind::MemReserve,
+                path: Vec::new(),
+                region: Region {
+                    address: *address as u128,
+                    size: *size as u128,
+                },
+                location: Some(loc.clone()),
+            });
+        }
+    }
+    found
+}
+
+// Reserved regions are carved out of memory, so those pairs are expected, as
+// is a node overlapping one of its own children (syscon and simple-mfd style).
+fn expected(a: &Claim, b: &Claim) -> bool {
+    let nested = |x: &Claim, y: &Claim| {
+        x.kind != Kind::MemReserve && y.path.len() >= x.path.len() && y.path.starts_with(&x.path)
+    };
+    match (a.kind, b.kind) {
+        (Kind::Memory, Kind::Reserved) | (Kind::Reserved, Kind::Memory) => true,
+        (Kind::Memory, Kind::MemReserve) | (Kind::MemReserve, Kind::Memory) => true,
+        (Kind::MemReserve, Kind::MemReserve) => false,
+        _ => nested(a, b) || nested(b, a),
+    }
+}
+
+pub fn collisions(root: &Root) -> Vec<(Claim, Claim)> {
+    let mut claims = claims(root);
+    claims.sort_by(|a, b| a.region.address.cmp(&b.region.address));
+    let mut found = Vec::new();
+    for (i, a) in claims.iter().enumerate() {
+        let end = a.region.address.saturating_add(a.region.size);
+        for b in claims[i + 1..]
+            .iter()
+            .take_while(|b| b.region.address < end)
+        {
+            if !expected(a, b) {
+                found.push((a.clone(), b.clone()));
+            }
+        }
+    }
+    found
+}
+
+pub fn print_collisions(root: &Root) {
+    for (a, b) in collisions(root) {
+        let what = match (a.kind, b.kind) {
+            (Kind::MemReserve, _) | (_, Kind::MemReserve) => "memreserve collision",
+            (Kind::Memory, _) | (_, Kind::Memory) => "overlaps memory",
+            (Kind::Reserved, _) | (_, Kind::Reserved) => "overlaps reserved memory",
+            _ => "overlap",
+        };
+        println!("{}: {} and {}", what, a.describe(), b.describe());
+    }
+}
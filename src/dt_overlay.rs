@@ -0,0 +1,83 @@
+use crate::dt_value::render;
+use crate::dt_write::{dts_node, expand_macros};
+use crate::root::{path_string, DTNode, Root};
+
+fn block(lines: &[String], indent: &str) -> String {
+    let mut out = String::new();
+    for (i, statement) in lines.iter().enumerate() {
+        // Nodes are set apart, as in `dts`.
+        if i > 0 && statement.ends_with("};\n") {
+            out.push('\n');
+        }
+        for l in statement.lines() {
+            out.push_str(indent);
+            out.push_str(l);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Collects, for each node that changed, the statements that change it,
+// parents before children.
+fn changes(
+    modified: &Root,
+    path: &mut Vec<String>,
+    old: &DTNode,
+    new: &DTNode,
+    targets: &mut Vec<(Vec<String>, Vec<String>)>,
+) {
+    let at = targets.len();
+    let mut lines = Vec::new();
+    for (name, value) in new.properties() {
+        if old.property(name) == Some(value) {
+            continue;
+        }
+        lines.push(match value {
+            Some(v) => format!("{} = {};", name, render(&expand_macros(modified, v))),
+            None => format!("{};", name),
+        });
+    }
+    for (name, _) in old.properties() {
+        if !new.has_property(name) {
+            lines.push(format!("/delete-property/ {};", name));
+        }
+    }
+    for c in old.children() {
+        if new.child(&c.name).is_none() {
+            lines.push(format!("/delete-node/ {};", c.name));
+        }
+    }
+    for c in new.children() {
+        path.push(c.name.clone());
+        match old.child(&c.name) {
+            Some(o) => changes(modified, path, o, c, targets),
+            None => lines.extend(dts_node(modified, path)),
+        }
+        path.pop();
+    }
+    if !lines.is_empty() {
+        targets.insert(at, (path.clone(), lines));
+    }
+}
+
+// An overlay that turns `base` into `modified`. A changed node with a label
+// in `base` is targeted as `&label`, any other by its path as `&{/path}`, so
+// dtc numbers every fragment itself.
+pub fn overlay(base: &Root, modified: &Root) -> String {
+    let mut targets = Vec::new();
+    if let (Some(old), Some(new)) = (base.get_node(&["/"]), modified.get_node(&["/"])) {
+        changes(modified, &mut vec!["/".to_string()], old, new, &mut targets);
+    }
+    let mut out = String::from("/dts-v1/;\n/plugin/;\n");
+    for (path, lines) in targets {
+        let target = match base.labels_for(&path).first() {
+            Some(l) => format!("&{}", l),
+            None => format!("&{{{}}}", path_string(&path)),
+        };
+        out.push_str(&format!("\n{} {{\n", target));
+        out.push_str(&block(&lines, "\t"));
+        out.push_str("};\n");
+    }
+    out
+}
@@ -0,0 +1,640 @@
+use crate::dt_lexer::{DTInfo, Span};
+use crate::dt_load::Source;
+use crate::dt_value::render;
+use crate::dt_write::dts_node;
+use crate::root::{DTNode, Location, Root};
+use std::fs;
+use std::io;
+
+const CONTEXT: usize = 3;
+
+// Changes to the input file that turn one tree into another. Whatever the
+// file already says is edited where it says it, so the rest of the text,
+// comments and layout included, stays as it was.
+pub struct Patch {
+    pub file: String,
+    original: String,
+    // Byte ranges of the original and what replaces them, in order.
+    edits: Vec<(usize, usize, String)>,
+}
+
+fn line_start(text: &str, at: usize) -> usize {
+    text[..at].rfind('\n').map_or(0, |i| i + 1)
+}
+
+// Just past the newline ending the line `at` is on.
+fn line_end(text: &str, at: usize) -> usize {
+    text[at..].find('\n').map_or(text.len(), |i| at + i + 1)
+}
+
+fn blank(text: &str) -> bool {
+    text.trim().is_empty()
+}
+
+fn indent(text: &str, by: &str) -> String {
+    let mut out = String::new();
+    for l in text.lines() {
+        if !l.is_empty() {
+            out.push_str(by);
+        }
+        out.push_str(l);
+        out.push('\n');
+    }
+    out
+}
+
+fn statement(name: &str, value: &Option<String>) -> String {
+    match value {
+        Some(v) => format!("{} = {};", name, render(v)),
+        None => format!("{};", name),
+    }
+}
+
+fn diff_line(out: &mut String, mark: char, line: &str) {
+    out.push(mark);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+impl Patch {
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    // The edited file.
+    pub fn apply(&self) -> String {
+        let mut out = String::new();
+        let mut at = 0;
+        for (start, end, text) in &self.edits {
+            out.push_str(&self.original[at..*start]);
+            out.push_str(text);
+            at = *end;
+        }
+        out.push_str(&self.original[at..]);
+        out
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        fs::write(&self.file, self.apply())
+    }
+
+    // Each run of touching edits widened to whole lines: the first line it
+    // replaces, how many and the lines replacing them.
+    fn changes(&self) -> Vec<(usize, usize, Vec<String>)> {
+        let text = &self.original;
+        let mut changes = Vec::new();
+        let lines = |s: usize, e: usize| {
+            let start = line_start(text, s);
+            match (e > s, s > start) {
+                (true, _) => (start, line_end(text, e - 1)),
+                (false, true) => (start, line_end(text, s)),
+                (false, false) => (start, start),
+            }
+        };
+        let mut i = 0;
+        while i < self.edits.len() {
+            let (start, mut end) = lines(self.edits[i].0, self.edits[i].1);
+            let mut j = i + 1;
+            while j < self.edits.len() && self.edits[j].0 <= end {
+                end = end.max(lines(self.edits[j].0, self.edits[j].1).1);
+                j += 1;
+            }
+            let mut content = String::new();
+            let mut at = start;
+            for (s, e, t) in &self.edits[i..j] {
+                content.push_str(&text[at..*s]);
+                content.push_str(t);
+                at = *e;
+            }
+            content.push_str(&text[at..end]);
+            changes.push((
+                text[..start].matches('\n').count(),
+                text[start..end].split_inclusive('\n').count(),
+                content.split_inclusive('\n').map(String::from).collect(),
+            ));
+            i = j;
+        }
+        changes
+    }
+
+    // The edits as a unified diff, for review or `patch -p1`.
+    pub fn diff(&self) -> String {
+        let old: Vec<&str> = self.original.split_inclusive('\n').collect();
+        let changes = self.changes();
+        let mut out = format!("--- a/{}\n+++ b/{}\n", self.file, self.file);
+        let mut shift: isize = 0;
+        let mut c = 0;
+        while c < changes.len() {
+            // Changes close enough to share context make one hunk.
+            let mut last = c;
+            while last + 1 < changes.len()
+                && changes[last + 1].0 <= changes[last].0 + changes[last].1 + 2 * CONTEXT
+            {
+                last += 1;
+            }
+            let from = changes[c].0.saturating_sub(CONTEXT);
+            let to = (changes[last].0 + changes[last].1 + CONTEXT).min(old.len());
+            let mut body = String::new();
+            let (mut old_n, mut new_n) = (0, 0);
+            let mut at = from;
+            for (first, count, lines) in &changes[c..=last] {
+                for l in &old[at..*first] {
+                    diff_line(&mut body, ' ', l);
+                }
+                for l in &old[*first..first + count] {
+                    diff_line(&mut body, '-', l);
+                }
+                for l in lines {
+                    diff_line(&mut body, '+', l);
+                }
+                old_n += first - at + count;
+                new_n += first - at + lines.len();
+                at = first + count;
+            }
+            for l in &old[at..to] {
+                diff_line(&mut body, ' ', l);
+            }
+            old_n += to - at;
+            new_n += to - at;
+            // An empty side is numbered by the line before it.
+            let old_start = from + (old_n > 0) as usize;
+            let new_start = (from as isize + shift) as usize + (new_n > 0) as usize;
+            out.push_str(&format!(
+                "@@ -{},{} +{},{} @@\n",
+                old_start, old_n, new_start, new_n
+            ));
+            out.push_str(&body);
+            shift += new_n as isize - old_n as isize;
+            c = last + 1;
+        }
+        out
+    }
+}
+
+// The `};` closing the block opened at token `open`.
+fn close(tokens: &[(DTInfo, Span)], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, (t, _)) in tokens.iter().enumerate().skip(open + 1) {
+        match t {
+            DTInfo::Node(_, _) | DTInfo::RefNode(_) => depth += 1,
+            DTInfo::NodeEnd if depth == 0 => return Some(i),
+            DTInfo::NodeEnd => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+struct Patcher<'a> {
+    sources: &'a [Source],
+    main: &'a Source,
+    before: &'a Root,
+    after: &'a Root,
+    edits: Vec<(usize, usize, String)>,
+    // Statements to add before the `};` token of a block, by its index.
+    added: Vec<(usize, String)>,
+    // Statements for nodes with no block in the file to put them in, which
+    // go at its end.
+    appended: Vec<(Vec<String>, String)>,
+}
+
+impl<'a> Patcher<'a> {
+    fn token(&self, loc: &Location) -> Option<usize> {
+        if loc.file != self.main.file {
+            return None;
+        }
+        self.main
+            .tokens
+            .iter()
+            .position(|(_, s)| s.line == loc.line && s.column == loc.column)
+    }
+
+    fn close(&self, open: usize) -> Option<usize> {
+        close(&self.main.tokens, open)
+    }
+
+    // The last block opening the node in the file.
+    fn block(&self, node: &DTNode) -> Option<(usize, usize)> {
+        node.locations()
+            .iter()
+            .rev()
+            .filter_map(|l| self.token(l))
+            .find_map(|open| Some((open, self.close(open)?)))
+    }
+
+    // Whether the node and everything in it is given once, in the file.
+    fn only_here(&self, node: &DTNode) -> bool {
+        node.locations().len() == 1
+            && self.token(&node.locations()[0]).is_some()
+            && node.children().into_iter().all(|c| self.only_here(c))
+    }
+
+    // Whether the property is set in only one of the blocks opening the node,
+    // in whichever file, so that taking that out takes the property away.
+    fn defined_once(&self, node: &DTNode, property: &str) -> bool {
+        let mut count = 0;
+        for l in node.locations() {
+            let tokens = match self.sources.iter().find(|s| s.file == l.file) {
+                Some(s) => &s.tokens,
+                None => continue,
+            };
+            let open = match tokens
+                .iter()
+                .position(|(_, s)| s.line == l.line && s.column == l.column)
+            {
+                Some(o) => o,
+                None => continue,
+            };
+            let end = close(tokens, open).unwrap_or(tokens.len());
+            let mut depth = 0;
+            for (t, _) in &tokens[open + 1..end] {
+                match t {
+                    DTInfo::Node(_, _) | DTInfo::RefNode(_) => depth += 1,
+                    DTInfo::NodeEnd => depth -= 1,
+                    DTInfo::Property(p, _) if depth == 0 && p == property => count += 1,
+                    _ => (),
+                }
+            }
+        }
+        count == 1
+    }
+
+    fn replace(&mut self, token: usize, text: String) {
+        let span = self.main.tokens[token].1;
+        self.edits.push((span.start, span.end, text));
+    }
+
+    // Takes out tokens `first..=last` with the comments above and after
+    // them, and their lines if nothing else is on them.
+    fn remove(&mut self, mut first: usize, mut last: usize) {
+        let (tokens, text) = (&self.main.tokens, &self.main.text);
+        while first > 0 {
+            let (t, s) = &tokens[first - 1];
+            let above = s.end_line + 1 >= tokens[first].1.line;
+            if matches!(t, DTInfo::Comment(_))
+                && above
+                && blank(&text[line_start(text, s.start)..s.start])
+            {
+                first -= 1;
+            } else {
+                break;
+            }
+        }
+        if let Some((DTInfo::Comment(_), s)) = tokens.get(last + 1) {
+            if s.line == tokens[last].1.end_line {
+                last += 1;
+            }
+        }
+        let (mut start, mut end) = (tokens[first].1.start, tokens[last].1.end);
+        let (ls, le) = (line_start(text, start), line_end(text, end));
+        if blank(&text[ls..start]) && blank(&text[end..le]) {
+            start = ls;
+            end = le;
+        } else {
+            start = ls + text[ls..start].trim_end().len();
+        }
+        self.edits.push((start, end, String::new()));
+    }
+
+    // Adds to the node's last block in the file, unless a later include
+    // could override it there.
+    fn add(&mut self, path: &[String], node: &DTNode, statement: &str) {
+        let tokens = &self.main.tokens;
+        let close = match self.block(node) {
+            Some((_, close)) => close,
+            None => return self.append(path, statement),
+        };
+        if tokens[close..]
+            .iter()
+            .any(|(t, _)| matches!(t, DTInfo::Include(_)))
+        {
+            return self.append(path, statement);
+        }
+        let statement = format!("{}\n", statement.trim_end_matches('\n'));
+        match self.added.iter_mut().find(|(c, _)| *c == close) {
+            Some((_, statements)) => statements.push_str(&statement),
+            None => self.added.push((close, statement)),
+        }
+    }
+
+    // One edit per block for everything added to it, before its `};`.
+    fn insert(&mut self, close: usize, statements: &str) {
+        let text = &self.main.text;
+        let at = self.main.tokens[close].1.start;
+        let ls = line_start(text, at);
+        let outer: String = text[ls..]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+        let lines = indent(statements, &format!("{}\t", outer));
+        if blank(&text[ls..at]) {
+            self.edits.push((ls, ls, lines));
+        } else {
+            // `};` shares its line with something else.
+            let end = ls + text[ls..at].trim_end().len();
+            self.edits.push((end, at, format!("\n{}{}", lines, outer)));
+        }
+    }
+
+    fn append(&mut self, path: &[String], statement: &str) {
+        self.appended.push((path.to_vec(), statement.to_string()));
+    }
+
+    fn node(&mut self, path: &mut Vec<String>, old: &DTNode, new: &DTNode) {
+        for (name, value) in new.properties() {
+            if old.property(name) == Some(value) {
+                continue;
+            }
+            let line = statement(name, value);
+            match old.property_location(name).and_then(|l| self.token(l)) {
+                Some(i) => self.replace(i, line),
+                None => self.add(path, old, &line),
+            }
+        }
+        for (name, _) in old.properties() {
+            if new.has_property(name) {
+                continue;
+            }
+            let delete = format!("/delete-property/ {};", name);
+            match old.property_location(name).and_then(|l| self.token(l)) {
+                Some(i) if self.defined_once(old, name) => self.remove(i, i),
+                Some(i) => self.replace(i, delete),
+                None => self.add(path, old, &delete),
+            }
+        }
+        for c in new.children() {
+            path.push(c.name.clone());
+            match old.child(&c.name) {
+                Some(o) => self.node(path, o, c),
+                None => {
+                    if let Some(text) = dts_node(self.after, path) {
+                        self.add(&path[..path.len() - 1], old, &text);
+                    }
+                }
+            }
+            path.pop();
+        }
+        for c in old.children() {
+            if new.child(&c.name).is_some() {
+                continue;
+            }
+            // At the end, so that no later `&label { }` opens the node again.
+            match self.block(c) {
+                Some((open, close)) if self.only_here(c) => self.remove(open, close),
+                _ => self.append(path, &format!("/delete-node/ {};", c.name)),
+            }
+        }
+    }
+
+    // The closest labelled node at or above `path` and the rest of the path.
+    fn anchor(&self, path: &[String]) -> (String, Vec<String>) {
+        for k in (0..path.len()).rev() {
+            if let Some(l) = self.before.labels_for(&path[..=k]).first() {
+                return (format!("&{}", l), path[k + 1..].to_vec());
+            }
+        }
+        ("/".to_string(), path.iter().skip(1).cloned().collect())
+    }
+
+    fn appended(&self) -> String {
+        let anchored: Vec<(String, Vec<String>, &String)> = self
+            .appended
+            .iter()
+            .map(|(path, statement)| {
+                let (anchor, rest) = self.anchor(path);
+                (anchor, rest, statement)
+            })
+            .collect();
+        let mut anchors: Vec<&String> = Vec::new();
+        for (a, _, _) in &anchored {
+            if !anchors.contains(&a) {
+                anchors.push(a);
+            }
+        }
+        let mut out = String::new();
+        for anchor in anchors {
+            let mut statements: Vec<(&Vec<String>, &String)> = anchored
+                .iter()
+                .filter(|(a, _, _)| a == anchor)
+                .map(|(_, rest, statement)| (rest, *statement))
+                .collect();
+            statements.sort_by(|a, b| a.0.cmp(b.0));
+            out.push_str(&format!("\n{} {{\n", anchor));
+            let mut open: Vec<String> = Vec::new();
+            for (rest, statement) in statements {
+                let common = open.iter().zip(rest).take_while(|(a, b)| a == b).count();
+                while open.len() > common {
+                    open.pop();
+                    out.push_str(&format!("{}}};\n", "\t".repeat(open.len() + 1)));
+                }
+                for name in &rest[common..] {
+                    out.push_str(&format!("{}{} {{\n", "\t".repeat(open.len() + 1), name));
+                    open.push(name.clone());
+                }
+                out.push_str(&indent(statement, &"\t".repeat(open.len() + 1)));
+            }
+            while open.pop().is_some() {
+                out.push_str(&format!("{}}};\n", "\t".repeat(open.len() + 1)));
+            }
+            out.push_str("};\n");
+        }
+        out
+    }
+}
+
+// The smallest change to the first of `sources` that makes it build `after`
+// instead of `before`, the tree it built. What the file says is changed in
+// place; changes to things only its includes say go in blocks at its end.
+// Labels, macros and /memreserve/ are left alone.
+// Without sources there is no file to change and the patch is empty.
+pub fn patch(sources: &[Source], before: &Root, after: &Root) -> Patch {
+    let main = match sources.first() {
+        Some(m) => m,
+        None => {
+            return Patch {
+                file: String::new(),
+                original: String::new(),
+                edits: Vec::new(),
+            }
+        }
+    };
+    let mut p = Patcher {
+        sources,
+        main,
+        before,
+        after,
+        edits: Vec::new(),
+        added: Vec::new(),
+        appended: Vec::new(),
+    };
+    if let (Some(old), Some(new)) = (before.get_node(&["/"]), after.get_node(&["/"])) {
+        p.node(&mut vec!["/".to_string()], old, new);
+    }
+    for (close, statements) in std::mem::take(&mut p.added) {
+        p.insert(close, &statements);
+    }
+    let mut edits = p.edits.clone();
+    if !p.appended.is_empty() {
+        let end = main.text.len();
+        let newline = if main.text.ends_with('\n') { "" } else { "\n" };
+        edits.push((end, end, format!("{}{}", newline, p.appended())));
+    }
+    edits.sort_by_key(|e| (e.0, e.1));
+    Patch {
+        file: main.file.clone(),
+        original: main.text.clone(),
+        edits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::patch;
+    use crate::dt_load::{build_with, load, sources, Options};
+    use crate::dt_write::dts;
+    use crate::root::Root;
+    use std::collections::HashMap;
+
+    // Loads `files`, the first being the input, applies `edit` to the tree
+    // and gives the patched input and its diff, checking that it builds the
+    // edited tree.
+    fn patched<F: FnOnce(&mut Root)>(files: &[(&str, &str)], edit: F) -> (String, String) {
+        let first = files[0].0;
+        let mut files: HashMap<String, String> = files
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        let options = Options::default();
+        let (loaded, before) = {
+            let read = |f: &str| files.get(f).cloned().ok_or_else(|| "missing".to_string());
+            let loaded = sources(first, &read, &options).unwrap();
+            let before = build_with(&loaded, first, &options).unwrap();
+            (loaded, before)
+        };
+        let mut after = before.clone();
+        edit(&mut after);
+        let p = patch(&loaded, &before, &after);
+        let diff = p.diff();
+        let text = p.apply();
+        files.insert(first.to_string(), text.clone());
+        let read = |f: &str| files.get(f).cloned().ok_or_else(|| "missing".to_string());
+        let rebuilt = load(first, &read, &options).unwrap();
+        assert_eq!(dts(&rebuilt), dts(&after));
+        (text, diff)
+    }
+
+    fn uart(r: &mut Root) -> Vec<String> {
+        r.label_path("uart0").cloned().unwrap()
+    }
+
+    #[test]
+    fn adds_to_one_line_block() {
+        let dts = "/dts-v1/;\n/ {\n\tuart0: serial { };\n};\n\n&uart0 { status = \"okay\"; };\n";
+        let (text, diff) = patched(&[("a.dts", dts)], |r| {
+            let p = uart(r);
+            r.set_property(&p, "current-speed", Some("<115200>"))
+                .unwrap();
+            r.set_property(&p, "wakeup-source", None).unwrap();
+        });
+        assert_eq!(
+            text,
+            "/dts-v1/;\n/ {\n\tuart0: serial { };\n};\n\n&uart0 { status = \"okay\";\n\tcurrent-speed = <115200>;\n\twakeup-source;\n};\n"
+        );
+        assert_eq!(
+            diff,
+            "--- a/a.dts\n+++ b/a.dts\n@@ -3,4 +3,7 @@\n \tuart0: serial { };\n };\n \n\
+             -&uart0 { status = \"okay\"; };\n+&uart0 { status = \"okay\";\n\
+             +\tcurrent-speed = <115200>;\n+\twakeup-source;\n+};\n"
+        );
+    }
+
+    #[test]
+    fn adds_to_multi_line_block() {
+        let dts = "/dts-v1/;\n/ {\n\tuart0: serial {\n\t\tstatus = \"okay\";\n\t};\n};\n";
+        let (text, diff) = patched(&[("a.dts", dts)], |r| {
+            let p = uart(r);
+            r.set_property(&p, "current-speed", Some("<115200>"))
+                .unwrap();
+            r.set_property(&p, "wakeup-source", None).unwrap();
+        });
+        assert_eq!(
+            text,
+            "/dts-v1/;\n/ {\n\tuart0: serial {\n\t\tstatus = \"okay\";\n\t\tcurrent-speed = <115200>;\n\t\twakeup-source;\n\t};\n};\n"
+        );
+        assert_eq!(
+            diff,
+            "--- a/a.dts\n+++ b/a.dts\n@@ -2,5 +2,7 @@\n / {\n \tuart0: serial {\n\
+             \x20\t\tstatus = \"okay\";\n+\t\tcurrent-speed = <115200>;\n\
+             +\t\twakeup-source;\n \t};\n };\n"
+        );
+    }
+
+    #[test]
+    fn appends_label_block_for_included_node() {
+        let files = [
+            ("a.dts", "/dts-v1/;\n#include \"soc.dtsi\"\n"),
+            (
+                "soc.dtsi",
+                "/ {\n\tuart0: serial {\n\t\tstatus = \"disabled\";\n\t};\n};\n",
+            ),
+        ];
+        let (text, diff) = patched(&files, |r| {
+            let p = uart(r);
+            r.delete_property(&p, "status").unwrap();
+            r.set_property(&p, "wakeup-source", None).unwrap();
+        });
+        assert_eq!(
+            text,
+            "/dts-v1/;\n#include \"soc.dtsi\"\n\n&uart0 {\n\twakeup-source;\n\t/delete-property/ status;\n};\n"
+        );
+        assert_eq!(
+            diff,
+            "--- a/a.dts\n+++ b/a.dts\n@@ -1,2 +1,7 @@\n /dts-v1/;\n #include \"soc.dtsi\"\n\
+             +\n+&uart0 {\n+\twakeup-source;\n+\t/delete-property/ status;\n+};\n"
+        );
+    }
+
+    #[test]
+    fn removes_property_set_once_for_its_node() {
+        let files = [
+            (
+                "a.dts",
+                "/dts-v1/;\n#include \"soc.dtsi\"\n&uart0 {\n\tstatus = \"okay\";\n};\n",
+            ),
+            (
+                "soc.dtsi",
+                "/ {\n\tuart0: serial { };\n\tuart1: serial1 {\n\t\tstatus = \"okay\";\n\t};\n};\n",
+            ),
+        ];
+        let (text, diff) = patched(&files, |r| {
+            let p = uart(r);
+            r.delete_property(&p, "status").unwrap();
+        });
+        assert_eq!(text, "/dts-v1/;\n#include \"soc.dtsi\"\n&uart0 {\n};\n");
+        assert_eq!(
+            diff,
+            "--- a/a.dts\n+++ b/a.dts\n@@ -1,5 +1,4 @@\n /dts-v1/;\n #include \"soc.dtsi\"\n\
+             \x20&uart0 {\n-\tstatus = \"okay\";\n };\n"
+        );
+    }
+
+    #[test]
+    fn later_hunks_are_numbered_after_earlier_ones() {
+        let dts = "/dts-v1/;\n/ {\n\ta: a {\n\t\tx;\n\t\ty;\n\t};\n\tp1;\n\tp2;\n\
+                   \tp3;\n\tp4;\n\tb: b {\n\t\tz;\n\t};\n};\n";
+        let (_, diff) = patched(&[("a.dts", dts)], |r| {
+            let a = r.label_path("a").cloned().unwrap();
+            let b = r.label_path("b").cloned().unwrap();
+            r.delete_property(&a, "y").unwrap();
+            r.set_property(&b, "w", None).unwrap();
+        });
+        assert_eq!(
+            diff,
+            "--- a/a.dts\n+++ b/a.dts\n\
+             @@ -2,7 +2,6 @@\n / {\n \ta: a {\n \t\tx;\n-\t\ty;\n \t};\n \tp1;\n \tp2;\n\
+             @@ -10,5 +9,6 @@\n \tp4;\n \tb: b {\n \t\tz;\n+\t\tw;\n \t};\n };\n"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use crate::dt_value::Cell;
+use crate::root::{path_string, Root};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spec {
+    pub target: Vec<String>,
+    pub args: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum SpecError {
+    Unresolved(String),
+    NoCells(Vec<String>, String),
+    Truncated(Vec<String>),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecError::Unresolved(s) => write!(f, "cannot resolve {}", s),
+            SpecError::NoCells(p, c) => write!(f, "{} has no {}", path_string(p), c),
+            SpecError::Truncated(p) => write!(f, "specifier for {} is cut short", path_string(p)),
+        }
+    }
+}
+
+fn cell_name(cell: &Cell) -> String {
+    match cell {
+        Cell::Num(n) => format!("{:#x}", n),
+        Cell::Ref(r) => format!("&{}", r),
+        Cell::Symbol(s) => s.clone(),
+    }
+}
+
+// Walks a `<&phandle arg...>` list where the number of args comes from the
+// target's `cells` property (#clock-cells, #gpio-cells, ...). A zero phandle
+// is an empty slot and comes back as None. Stops at the first error since the
+// stride of what follows is unknown.
+pub fn specifiers(
+    root: &Root,
+    raw: &Option<String>,
+    cells: &str,
+) -> Vec<Result<Option<Spec>, SpecError>> {
+    let list = root.cells(raw);
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < list.len() {
+        if list[i] == Cell::Num(0) {
+            found.push(Ok(None));
+            i += 1;
+            continue;
+        }
+        let target = match root.resolve_phandle(&list[i]) {
+            Some(t) => t,
+            None => {
+                found.push(Err(SpecError::Unresolved(cell_name(&list[i]))));
+                break;
+            }
+        };
+        let n = match root
+            .get_node(&target)
+            .and_then(|n| root.cell_value(n, cells))
+        {
+            Some(n) => n as usize,
+            None => {
+                found.push(Err(SpecError::NoCells(target, cells.to_string())));
+                break;
+            }
+        };
+        if i + 1 + n > list.len() {
+            found.push(Err(SpecError::Truncated(target)));
+            break;
+        }
+        let mut args = Vec::new();
+        for c in &list[i + 1..i + 1 + n] {
+            match c {
+                Cell::Num(v) => args.push(*v),
+                c => {
+                    found.push(Err(SpecError::Unresolved(cell_name(c))));
+                    return found;
+                }
+            }
+        }
+        found.push(Ok(Some(Spec { target, args })));
+        i += 1 + n;
+    }
+    found
+}
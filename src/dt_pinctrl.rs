@@ -0,0 +1,241 @@
+use crate::dt_value::{strings, Cell};
+use crate::root::{path_string, Root};
+use std::collections::BTreeMap;
+
+pub struct PinUse {
+    pub controller: Vec<String>,
+    pub pin: String,
+    pub function: Option<String>,
+    pub consumer: Vec<String>,
+    pub state: String,
+    pub group: Vec<String>,
+    pub enabled: bool,
+}
+
+fn numbers(root: &Root, raw: &Option<String>) -> Vec<Option<u32>> {
+    root.cells(raw)
+        .into_iter()
+        .map(|c| match c {
+            Cell::Num(n) => Some(n),
+            _ => None,
+        })
+        .collect()
+}
+
+// The pins and functions a single group node muxes. `pins`/`groups` with
+// `function` is the generic binding; `pinmux` cells carry the pin in the upper
+// bits and the function in the low byte (MediaTek, STM32, ...); and
+// `rockchip,pins` is (bank pin function &config) tuples.
+fn muxes(root: &Root, path: &[String]) -> Vec<(String, Option<String>)> {
+    let node = match root.get_node(path) {
+        Some(n) => n,
+        None => return Vec::new(),
+    };
+    let function = node
+        .property("function")
+        .map(strings)
+        .and_then(|f| f.first().cloned());
+    let mut found = Vec::new();
+    for p in ["pins", "groups"].iter() {
+        if let Some(raw) = node.property(p) {
+            for pin in strings(raw) {
+                found.push((pin, function.clone()));
+            }
+        }
+    }
+    if let Some(raw) = node.property("pinmux") {
+        for v in numbers(root, raw) {
+            match v {
+                Some(v) => found.push((format!("{}", v >> 8), Some(format!("{}", v & 0xff)))),
+                None => eprintln!(
+                    "warning: {}: pinmux entry is not a number",
+                    path_string(path)
+                ),
+            }
+        }
+    }
+    if let Some(raw) = node.property("rockchip,pins") {
+        for t in numbers(root, raw).chunks(4) {
+            if let [Some(bank), Some(pin), Some(func), ..] = t {
+                found.push((format!("bank{}-{}", bank, pin), Some(format!("{}", func))));
+            }
+        }
+    }
+    found
+}
+
+// A state phandle points at a group node or at a node whose children are the
+// groups, so look one level down as well.
+fn groups(root: &Root, path: &[String]) -> Vec<Vec<String>> {
+    let mut found = Vec::new();
+    if !muxes(root, path).is_empty() {
+        found.push(path.to_vec());
+    }
+    if let Some(node) = root.get_node(path) {
+        for c in node.children() {
+            let mut p = path.to_vec();
+            p.push(c.name.clone());
+            if !muxes(root, &p).is_empty() {
+                found.push(p);
+            }
+        }
+    }
+    found
+}
+
+fn controller(root: &Root, path: &[String]) -> Vec<String> {
+    (1..path.len())
+        .rev()
+        .map(|i| &path[..i])
+        .find(|p| match root.get_node(p) {
+            Some(n) => n.has_property("compatible"),
+            None => false,
+        })
+        .unwrap_or(&path[..path.len() - 1])
+        .to_vec()
+}
+
+pub fn uses(root: &Root) -> Vec<PinUse> {
+    let mut found = Vec::new();
+    for (path, node) in root.nodes() {
+        let names = node
+            .property("pinctrl-names")
+            .map(strings)
+            .unwrap_or_default();
+        let enabled = root.is_enabled(&path);
+        let mut index = 0;
+        while let Some(raw) = node.property(&format!("pinctrl-{}", index)) {
+            let state = names
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| index.to_string());
+            for cell in root.cells(raw) {
+                let config = match root.resolve_phandle(&cell) {
+                    Some(c) => c,
+                    None => {
+                        eprintln!(
+                            "warning: {}: pinctrl-{} references unknown {:?}",
+                            path_string(&path),
+                            index,
+                            cell
+                        );
+                        continue;
+                    }
+                };
+                for group in groups(root, &config) {
+                    for (pin, function) in muxes(root, &group) {
+                        found.push(PinUse {
+                            controller: controller(root, &group),
+                            pin,
+                            function,
+                            consumer: path.clone(),
+                            state: state.clone(),
+                            group: group.clone(),
+                            enabled,
+                        });
+                    }
+                }
+            }
+            index += 1;
+        }
+    }
+    found
+}
+
+fn is_default(u: &PinUse) -> bool {
+    u.state == "default" || u.state == "init" || u.state == "0"
+}
+
+pub fn print_pinctrl(root: &Root) {
+    let mut by_controller: BTreeMap<Vec<String>, Vec<PinUse>> = BTreeMap::new();
+    for u in uses(root) {
+        by_controller
+            .entry(u.controller.clone())
+            .or_insert_with(Vec::new)
+            .push(u);
+    }
+    for (controller, mut pins) in by_controller {
+        pins.sort_by(|a, b| (&a.pin, &a.consumer).cmp(&(&b.pin, &b.consumer)));
+        println!("{}", path_string(&controller));
+        let width = pins.iter().map(|u| u.pin.len()).max().unwrap_or(0);
+        for u in &pins {
+            println!(
+                "    {:<w$}  {:<12} {} {} ({}){}",
+                u.pin,
+                u.function.as_deref().unwrap_or("-"),
+                path_string(&u.consumer),
+                u.state,
+                path_string(&u.group),
+                if u.enabled { "" } else { " (disabled)" },
+                w = width
+            );
+        }
+    }
+    for c in conflicts(root) {
+        eprintln!("warning: {}", c);
+    }
+}
+
+// Pins muxed to different functions by two enabled devices. Only the state
+// applied at probe can clash; sleep and idle states are alternatives for the
+// same device.
+pub fn conflicts(root: &Root) -> Vec<String> {
+    let mut active: Vec<PinUse> = uses(root)
+        .into_iter()
+        .filter(|u| u.enabled && u.function.is_some() && is_default(u))
+        .collect();
+    active.sort_by(|a, b| {
+        (&a.controller, &a.pin, &a.consumer).cmp(&(&b.controller, &b.pin, &b.consumer))
+    });
+    let mut found = Vec::new();
+    for (i, a) in active.iter().enumerate() {
+        for b in active[i + 1..].iter().filter(|b| {
+            b.controller == a.controller
+                && b.pin == a.pin
+                && b.consumer != a.consumer
+                && b.function != a.function
+        }) {
+            found.push(format!(
+                "pin {} of {} muxed as {} by {} and as {} by {}",
+                a.pin,
+                path_string(&a.controller),
+                a.function.as_deref().unwrap_or("-"),
+                path_string(&a.consumer),
+                b.function.as_deref().unwrap_or("-"),
+                path_string(&b.consumer)
+            ));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::conflicts;
+    use crate::{load_str, Options};
+
+    #[test]
+    fn pins_muxed_twice() {
+        let dts = "/dts-v1/;\n/ {\n\
+            \tpinctrl { compatible = \"vendor,pinctrl\";\n\
+            \t\tuart_pins: uart { pins = \"PA1\", \"PA2\"; function = \"uart\"; };\n\
+            \t\tspi_pins: spi { pins = \"PA2\"; function = \"spi\"; };\n\
+            \t\ti2c_pins: i2c { pinmux = <0x305 0x402>; };\n\
+            \t\tpwm_pins: pwm { pinmux = <0x307>; };\n\
+            \t\tidle: idle { pins = \"PA1\"; function = \"gpio\"; };\n\t};\n\
+            \tuart { pinctrl-0 = <&uart_pins>; pinctrl-1 = <&idle>;\n\
+            \t\tpinctrl-names = \"default\", \"sleep\"; };\n\
+            \tspi { pinctrl-0 = <&spi_pins>; pinctrl-names = \"default\"; };\n\
+            \tgpio { pinctrl-0 = <&idle>; status = \"disabled\"; };\n\
+            \ti2c { pinctrl-0 = <&i2c_pins>; };\n\
+            \tpwm { pinctrl-0 = <&pwm_pins>; };\n};\n";
+        let root = load_str("a.dts", dts, &Options::default()).unwrap();
+        assert_eq!(
+            conflicts(&root),
+            vec![
+                "pin 3 of /pinctrl muxed as 5 by /i2c and as 7 by /pwm",
+                "pin PA2 of /pinctrl muxed as spi by /spi and as uart by /uart",
+            ]
+        );
+    }
+}
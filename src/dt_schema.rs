@@ -0,0 +1,785 @@
+extern crate regex;
+extern crate yaml_rust;
+
+use crate::dt_address::decode_reg;
+use crate::dt_irq;
+use crate::dt_phandle::specifiers;
+use crate::dt_value::{parse, strings, Cell, Value};
+use crate::root::{location, path_string, Location, Root};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use yaml_rust::{Yaml, YamlLoader};
+
+const SCHEMA_BASE: &str = "http://devicetree.org";
+
+pub struct Schema {
+    pub file: String,
+    pub id: String,
+    pub doc: Yaml,
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Io(String, io::Error),
+    Yaml(String, yaml_rust::ScanError),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Io(p, e) => write!(f, "{}: {}", p, e),
+            SchemaError::Yaml(p, e) => write!(f, "{}: {}", p, e),
+        }
+    }
+}
+
+pub struct Violation {
+    pub path: Vec<String>,
+    pub location: Option<Location>,
+    pub message: String,
+    pub schema: String,
+}
+
+pub fn load_dir(dir: &Path) -> Result<Vec<Schema>, SchemaError> {
+    let mut found = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => return Err(SchemaError::Io(dir.display().to_string(), e)),
+    };
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+    for p in paths {
+        if p.is_dir() {
+            found.append(&mut load_dir(&p)?);
+        } else if p.extension().map_or(false, |e| e == "yaml") {
+            let name = p.display().to_string();
+            let text = match fs::read_to_string(&p) {
+                Ok(t) => t,
+                Err(e) => return Err(SchemaError::Io(name, e)),
+            };
+            let docs = match YamlLoader::load_from_str(&text) {
+                Ok(d) => d,
+                Err(e) => return Err(SchemaError::Yaml(name, e)),
+            };
+            for doc in docs {
+                // Only json-schema documents carry an $id; skip example data.
+                if let Some(id) = doc["$id"].as_str() {
+                    found.push(Schema {
+                        file: name.clone(),
+                        id: id.trim_end_matches('#').to_string(),
+                        doc,
+                    });
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn join_id(base: &str, rel: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for p in rel.split('/') {
+        match p {
+            ".." => {
+                parts.pop();
+            }
+            "." => (),
+            p => parts.push(p),
+        }
+    }
+    parts.join("/")
+}
+
+fn pointer<'a>(doc: &'a Yaml, fragment: &str) -> Option<&'a Yaml> {
+    let mut y = doc;
+    for key in fragment.split('/').filter(|k| !k.is_empty()) {
+        y = &y[key];
+        if y.is_badvalue() {
+            return None;
+        }
+    }
+    Some(y)
+}
+
+fn is_types_ref(r: &str) -> bool {
+    r.split('#')
+        .next()
+        .map_or(false, |f| f.ends_with("types.yaml"))
+}
+
+fn type_name(r: &str) -> Option<&str> {
+    if is_types_ref(r) {
+        r.rsplit('/').next()
+    } else {
+        None
+    }
+}
+
+fn resolve<'a>(
+    schemas: &'a [Schema],
+    current: &'a Schema,
+    r: &str,
+) -> Option<(&'a Schema, &'a Yaml)> {
+    let mut split = r.splitn(2, '#');
+    let file = split.next().unwrap_or("");
+    let fragment = split.next().unwrap_or("");
+    let schema = if file.is_empty() {
+        current
+    } else {
+        let id = if file.starts_with("http") {
+            file.to_string()
+        } else if file.starts_with('/') {
+            format!("{}{}", SCHEMA_BASE, file)
+        } else {
+            join_id(&current.id, file)
+        };
+        schemas.iter().find(|s| s.id == id)?
+    };
+    Some((schema, pointer(&schema.doc, fragment)?))
+}
+
+fn collect_strings(y: &Yaml, found: &mut Vec<String>) {
+    match y {
+        Yaml::Hash(h) => {
+            for (k, v) in h {
+                match (k.as_str(), v) {
+                    (Some("const"), Yaml::String(s)) => found.push(s.clone()),
+                    (Some("enum"), Yaml::Array(a)) => {
+                        found.extend(a.iter().filter_map(|s| s.as_str().map(|s| s.to_string())))
+                    }
+                    _ => collect_strings(v, found),
+                }
+            }
+        }
+        Yaml::Array(a) => {
+            for v in a {
+                collect_strings(v, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+pub fn compatibles(schema: &Schema) -> Vec<String> {
+    let mut found = Vec::new();
+    if schema.doc["select"] == Yaml::Boolean(false) {
+        return found;
+    }
+    collect_strings(&schema.doc["properties"]["compatible"], &mut found);
+    collect_strings(
+        &schema.doc["select"]["properties"]["compatible"],
+        &mut found,
+    );
+    found
+}
+
+struct Check<'a> {
+    root: &'a Root,
+    schemas: &'a [Schema],
+    top: &'a Schema,
+    out: Vec<Violation>,
+    // $refs being followed for a node or property, so a schema that refers
+    // back to itself stops instead of recursing forever.
+    active: HashSet<(String, String, Vec<String>, Option<String>)>,
+}
+
+const ALWAYS_ALLOWED: &[&str] = &[
+    "status",
+    "phandle",
+    "linux,phandle",
+    "pinctrl-names",
+    "assigned-clocks",
+    "assigned-clock-parents",
+    "assigned-clock-rates",
+    "secure-status",
+];
+
+fn cells_name(property: &str) -> Option<String> {
+    if property.ends_with("-gpios") || property == "gpios" {
+        return Some("#gpio-cells".to_string());
+    }
+    let known = [
+        ("clocks", "#clock-cells"),
+        ("resets", "#reset-cells"),
+        ("dmas", "#dma-cells"),
+        ("phys", "#phy-cells"),
+        ("power-domains", "#power-domain-cells"),
+        ("pwms", "#pwm-cells"),
+        ("mboxes", "#mbox-cells"),
+        ("iommus", "#iommu-cells"),
+        ("io-channels", "#io-channel-cells"),
+        ("interconnects", "#interconnect-cells"),
+        ("thermal-sensors", "#thermal-sensor-cells"),
+    ];
+    known
+        .iter()
+        .find(|(p, _)| *p == property)
+        .map(|(_, c)| c.to_string())
+}
+
+fn yaml_num(y: &Yaml) -> Option<i64> {
+    match y {
+        Yaml::Integer(i) => Some(*i),
+        _ => None,
+    }
+}
+
+impl<'a> Check<'a> {
+    fn report(&mut self, path: &[String], property: Option<&str>, message: String) {
+        self.out.push(Violation {
+            path: path.to_vec(),
+            location: location(self.root, path, property),
+            message,
+            schema: self.top.file.clone(),
+        });
+    }
+
+    fn values(&self, path: &[String], name: &str) -> Option<Vec<Value>> {
+        let raw = self.root.get_node(path)?.property(name)?;
+        Some(match raw {
+            Some(r) => parse(r),
+            None => Vec::new(),
+        })
+    }
+
+    fn item_count(&self, path: &[String], name: &str) -> usize {
+        let values = self.values(path, name).unwrap_or_default();
+        if values.iter().all(|v| matches!(v, Value::Str(_))) {
+            return values.len();
+        }
+        if name == "reg" {
+            if let Some(Ok(r)) = decode_reg(self.root, path) {
+                return r.len();
+            }
+        }
+        if name == "interrupts" || name == "interrupts-extended" {
+            if let Some(r) = dt_irq::resolve(self.root, path) {
+                return r.irqs.len();
+            }
+        }
+        if let Some(cells) = cells_name(name) {
+            let raw = self.root.get_node(path).and_then(|n| n.property(name));
+            if let Some(raw) = raw {
+                let specs = specifiers(self.root, raw, &cells);
+                if specs.iter().all(|s| s.is_ok()) {
+                    return specs.len();
+                }
+            }
+        }
+        let groups: Vec<&Vec<Cell>> = values
+            .iter()
+            .filter_map(|v| match v {
+                Value::Cells(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        match groups.as_slice() {
+            [one] => one.len(),
+            g => g.len(),
+        }
+    }
+
+    // The values a property's items are compared against for enum/const.
+    fn items(&self, path: &[String], name: &str) -> Vec<Yaml> {
+        let raw = match self.root.get_node(path).and_then(|n| n.property(name)) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let mut found: Vec<Yaml> = strings(raw).into_iter().map(Yaml::String).collect();
+        found.extend(self.root.cells(raw).into_iter().map(|c| match c {
+            Cell::Num(n) => Yaml::Integer(n as i64),
+            _ => Yaml::Null,
+        }));
+        found
+    }
+
+    fn check_type(&mut self, path: &[String], name: &str, t: &str) {
+        let values = match self.values(path, name) {
+            Some(v) => v,
+            None => return,
+        };
+        let strings = values.iter().filter(|v| matches!(v, Value::Str(_))).count();
+        let cells: Vec<&Vec<Cell>> = values
+            .iter()
+            .filter_map(|v| match v {
+                Value::Cells(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        let ok = match t {
+            "flag" | "boolean" => values.is_empty(),
+            "string" => strings == 1 && values.len() == 1,
+            "string-array" | "non-unique-string-array" => strings > 0 && strings == values.len(),
+            "uint32" | "int32" => cells.len() == 1 && cells[0].len() == 1,
+            "uint64" | "int64" => cells.len() == 1 && cells[0].len() == 2,
+            "phandle" => {
+                cells.len() == 1 && cells[0].len() == 1 && !matches!(cells[0][0], Cell::Symbol(_))
+            }
+            t if t.starts_with("uint") || t.starts_with("int") || t.starts_with("phandle") => {
+                !values.is_empty() && cells.len() == values.len()
+            }
+            _ => true,
+        };
+        if !ok {
+            self.report(path, Some(name), format!("{} is not of type {}", name, t));
+        }
+    }
+
+    fn check_values(&mut self, path: &[String], name: &str, s: &Yaml) {
+        let items = self.items(path, name);
+        if let Yaml::Array(allowed) = &s["enum"] {
+            if let Some(v) = items.iter().find(|v| !allowed.contains(v)) {
+                self.report(
+                    path,
+                    Some(name),
+                    format!("{} value {:?} is not one of {:?}", name, v, allowed),
+                );
+            }
+        }
+        if !s["const"].is_badvalue() && items.first() != Some(&s["const"]) {
+            self.report(
+                path,
+                Some(name),
+                format!("{} must be {:?}", name, s["const"]),
+            );
+        }
+        if !s["contains"].is_badvalue() {
+            let c = &s["contains"];
+            let matches = |v: &Yaml| match &c["enum"] {
+                Yaml::Array(allowed) => allowed.contains(v),
+                _ => c["const"] == *v,
+            };
+            if !items.iter().any(matches) {
+                self.report(path, Some(name), format!("{} has no matching item", name));
+            }
+        }
+        if let Some(min) = yaml_num(&s["minimum"]) {
+            if items.iter().filter_map(yaml_num).any(|v| v < min) {
+                self.report(path, Some(name), format!("{} is below {}", name, min));
+            }
+        }
+        if let Some(max) = yaml_num(&s["maximum"]) {
+            if items.iter().filter_map(yaml_num).any(|v| v > max) {
+                self.report(path, Some(name), format!("{} is above {}", name, max));
+            }
+        }
+    }
+
+    fn check_property(&mut self, schema: &'a Schema, path: &[String], name: &str, s: &'a Yaml) {
+        if *s == Yaml::Boolean(false) {
+            self.report(path, Some(name), format!("{} is not allowed", name));
+            return;
+        }
+        if let Some(r) = s["$ref"].as_str() {
+            match type_name(r) {
+                Some(t) => self.check_type(path, name, t),
+                None => {
+                    if let Some((sc, y)) = resolve(self.schemas, schema, r) {
+                        let key = (
+                            schema.id.clone(),
+                            r.to_string(),
+                            path.to_vec(),
+                            Some(name.to_string()),
+                        );
+                        if self.active.insert(key.clone()) {
+                            self.check_property(sc, path, name, y);
+                            self.active.remove(&key);
+                        }
+                    }
+                }
+            }
+        }
+        if s["type"].as_str() == Some("boolean") {
+            self.check_type(path, name, "flag");
+        }
+        if let Yaml::Array(all) = &s["allOf"] {
+            for sub in all {
+                self.check_property(schema, path, name, sub);
+            }
+        }
+        for key in ["oneOf", "anyOf"].iter() {
+            if let Yaml::Array(any) = &s[*key] {
+                let mut passed = 0;
+                for sub in any {
+                    let start = self.out.len();
+                    self.check_property(schema, path, name, sub);
+                    if self.out.len() == start {
+                        passed += 1;
+                    }
+                    self.out.truncate(start);
+                }
+                if passed == 0 {
+                    self.report(
+                        path,
+                        Some(name),
+                        format!("{} matches none of the {} alternatives", name, key),
+                    );
+                } else if passed > 1 && *key == "oneOf" {
+                    self.report(
+                        path,
+                        Some(name),
+                        format!("{} matches {} of the oneOf alternatives", name, passed),
+                    );
+                }
+            }
+        }
+        self.check_values(path, name, s);
+
+        let count = self.item_count(path, name);
+        let mut min = yaml_num(&s["minItems"]);
+        let mut max = yaml_num(&s["maxItems"]);
+        match &s["items"] {
+            Yaml::Array(list) => {
+                // dt-schema fixes up a plain items list to an exact count.
+                max = max.or(Some(list.len() as i64));
+                min = min.or(Some(list.len() as i64));
+                let items = self.items(path, name);
+                for (i, sub) in list.iter().enumerate() {
+                    if let Some(v) = items.get(i) {
+                        if let Yaml::Array(allowed) = &sub["enum"] {
+                            if !allowed.contains(v) {
+                                self.report(
+                                    path,
+                                    Some(name),
+                                    format!(
+                                        "{} item {} {:?} is not one of {:?}",
+                                        name, i, v, allowed
+                                    ),
+                                );
+                            }
+                        }
+                        if !sub["const"].is_badvalue() && sub["const"] != *v {
+                            self.report(
+                                path,
+                                Some(name),
+                                format!("{} item {} must be {:?}", name, i, sub["const"]),
+                            );
+                        }
+                    }
+                }
+            }
+            Yaml::Hash(_) => self.check_values(path, name, &s["items"]),
+            _ => (),
+        }
+        if let Some(min) = min {
+            if (count as i64) < min {
+                self.report(
+                    path,
+                    Some(name),
+                    format!("{} has {} items, fewer than {}", name, count, min),
+                );
+            }
+        }
+        if let Some(max) = max {
+            if count as i64 > max {
+                self.report(
+                    path,
+                    Some(name),
+                    format!("{} has {} items, more than {}", name, count, max),
+                );
+            }
+        }
+    }
+
+    fn is_node_schema(s: &Yaml) -> bool {
+        s["type"].as_str() == Some("object")
+            || !s["properties"].is_badvalue()
+            || !s["required"].is_badvalue()
+            || !s["patternProperties"].is_badvalue()
+    }
+
+    // Applies one schema object to a node, noting which properties it accounts
+    // for so the caller can enforce additionalProperties/unevaluatedProperties.
+    fn check_node(
+        &mut self,
+        schema: &'a Schema,
+        path: &[String],
+        s: &'a Yaml,
+        evaluated: &mut HashSet<String>,
+    ) {
+        let node = match self.root.get_node(path) {
+            Some(n) => n,
+            None => return,
+        };
+        if let Some(r) = s["$ref"].as_str() {
+            if !is_types_ref(r) {
+                if let Some((sc, y)) = resolve(self.schemas, schema, r) {
+                    let key = (schema.id.clone(), r.to_string(), path.to_vec(), None);
+                    if self.active.insert(key.clone()) {
+                        self.check_node(sc, path, y, evaluated);
+                        self.active.remove(&key);
+                    }
+                }
+            }
+        }
+        if let Yaml::Array(all) = &s["allOf"] {
+            for sub in all {
+                self.check_node(schema, path, sub, evaluated);
+            }
+        }
+        if !s["if"].is_badvalue() {
+            let branch = match self.trial(schema, path, &s["if"]) {
+                Some(found) => {
+                    evaluated.extend(found);
+                    &s["then"]
+                }
+                None => &s["else"],
+            };
+            if !branch.is_badvalue() {
+                self.check_node(schema, path, branch, evaluated);
+            }
+        }
+        for key in ["oneOf", "anyOf"].iter() {
+            if let Yaml::Array(any) = &s[*key] {
+                let passed: Vec<HashSet<String>> = any
+                    .iter()
+                    .filter_map(|sub| self.trial(schema, path, sub))
+                    .collect();
+                if passed.is_empty() {
+                    self.report(
+                        path,
+                        None,
+                        format!("node matches none of the {} alternatives", key),
+                    );
+                } else if passed.len() > 1 && *key == "oneOf" {
+                    self.report(
+                        path,
+                        None,
+                        format!("node matches {} of the oneOf alternatives", passed.len()),
+                    );
+                }
+                evaluated.extend(passed.into_iter().flatten());
+            }
+        }
+        if let Yaml::Array(required) = &s["required"] {
+            for r in required.iter().filter_map(|r| r.as_str()) {
+                if !node.has_property(r) && node.child(r).is_none() {
+                    self.report(path, None, format!("missing required property {}", r));
+                }
+            }
+        }
+        if let Yaml::Hash(props) = &s["properties"] {
+            for (k, sub) in props {
+                let name = match k.as_str() {
+                    Some(n) => n,
+                    None => continue,
+                };
+                evaluated.insert(name.to_string());
+                if node.has_property(name) {
+                    self.check_property(schema, path, name, sub);
+                } else if node.child(name).is_some() && Self::is_node_schema(sub) {
+                    let mut child = path.to_vec();
+                    child.push(name.to_string());
+                    self.check_child(schema, &child, sub);
+                }
+            }
+        }
+        if let Yaml::Hash(patterns) = &s["patternProperties"] {
+            for (k, sub) in patterns {
+                let re = match k.as_str().map(Regex::new) {
+                    Some(Ok(re)) => re,
+                    _ => continue,
+                };
+                for (name, _) in node.properties() {
+                    if re.is_match(name) {
+                        evaluated.insert(name.clone());
+                        self.check_property(schema, path, name, sub);
+                    }
+                }
+                if Self::is_node_schema(sub) {
+                    for c in node.children() {
+                        let base = c.name.split('@').next().unwrap_or("");
+                        if re.is_match(&c.name) || re.is_match(base) {
+                            let mut child = path.to_vec();
+                            child.push(c.name.clone());
+                            self.check_child(schema, &child, sub);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Applies a subschema without reporting, giving the properties it
+    // evaluated if the node matched it.
+    fn trial(
+        &mut self,
+        schema: &'a Schema,
+        path: &[String],
+        s: &'a Yaml,
+    ) -> Option<HashSet<String>> {
+        let start = self.out.len();
+        let mut evaluated = HashSet::new();
+        self.check_node(schema, path, s, &mut evaluated);
+        let passed = self.out.len() == start;
+        self.out.truncate(start);
+        if passed {
+            Some(evaluated)
+        } else {
+            None
+        }
+    }
+
+    fn check_child(&mut self, schema: &'a Schema, path: &[String], s: &'a Yaml) {
+        let mut evaluated = HashSet::new();
+        self.check_node(schema, path, s, &mut evaluated);
+        self.check_additional(path, s, &evaluated);
+    }
+
+    fn check_additional(&mut self, path: &[String], s: &Yaml, evaluated: &HashSet<String>) {
+        let closed = s["additionalProperties"] == Yaml::Boolean(false)
+            || s["unevaluatedProperties"] == Yaml::Boolean(false);
+        if !closed {
+            return;
+        }
+        let node = match self.root.get_node(path) {
+            Some(n) => n,
+            None => return,
+        };
+        let unknown: Vec<String> = node
+            .properties()
+            .into_iter()
+            .map(|(n, _)| n.clone())
+            .filter(|n| {
+                !evaluated.contains(n)
+                    && !ALWAYS_ALLOWED.contains(&n.as_str())
+                    && !n.starts_with("pinctrl-")
+            })
+            .collect();
+        for n in unknown {
+            self.report(path, Some(&n), format!("unknown property {}", n));
+        }
+    }
+}
+
+pub fn validate(root: &Root, schemas: &[Schema]) -> Vec<Violation> {
+    let matchers: Vec<(&Schema, Vec<String>)> =
+        schemas.iter().map(|s| (s, compatibles(s))).collect();
+    let mut out = Vec::new();
+    for (path, node) in root.nodes() {
+        let compatible = match node.property("compatible") {
+            Some(c) => strings(c),
+            None => continue,
+        };
+        for (schema, compats) in &matchers {
+            if !compatible.iter().any(|c| compats.contains(c)) {
+                continue;
+            }
+            let mut check = Check {
+                root,
+                schemas,
+                top: schema,
+                out: Vec::new(),
+                active: HashSet::new(),
+            };
+            check.check_child(schema, &path, &schema.doc);
+            out.append(&mut check.out);
+        }
+    }
+    out
+}
+
+pub fn print_violations(violations: &[Violation]) {
+    for v in violations {
+        let at = match &v.location {
+            Some(l) => format!("{}: ", l),
+            None => String::new(),
+        };
+        println!(
+            "{}error: {}: {} [{}]",
+            at,
+            path_string(&v.path),
+            v.message,
+            v.schema
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, Schema};
+    use crate::{load_str, Options};
+    use yaml_rust::YamlLoader;
+
+    fn violations(schema: &str, body: &str) -> Vec<String> {
+        let doc = YamlLoader::load_from_str(schema).unwrap().remove(0);
+        let schemas = vec![Schema {
+            file: "test.yaml".to_string(),
+            id: "http://devicetree.org/schemas/test.yaml".to_string(),
+            doc,
+        }];
+        let dts = format!(
+            "/dts-v1/;\n/ {{\n\tdev {{ compatible = \"v,dev\";{} }};\n}};\n",
+            body
+        );
+        let root = load_str("a.dts", &dts, &Options::default()).unwrap();
+        validate(&root, &schemas)
+            .into_iter()
+            .map(|v| v.message)
+            .collect()
+    }
+
+    const ONE_OF: &str = "
+properties:
+  compatible:
+    const: v,dev
+  mode:
+    oneOf:
+      - enum: [ fast, slow ]
+      - enum: [ fast, off ]
+oneOf:
+  - required: [ a ]
+  - required: [ b ]
+";
+
+    #[test]
+    fn one_of_needs_exactly_one_match() {
+        assert!(violations(ONE_OF, " a; mode = \"slow\";").is_empty());
+        assert_eq!(
+            violations(ONE_OF, " a; b; mode = \"fast\";"),
+            vec![
+                "node matches 2 of the oneOf alternatives",
+                "mode matches 2 of the oneOf alternatives",
+            ]
+        );
+        assert_eq!(
+            violations(ONE_OF, " mode = \"on\";"),
+            vec![
+                "node matches none of the oneOf alternatives",
+                "mode matches none of the oneOf alternatives",
+            ]
+        );
+    }
+
+    #[test]
+    fn cyclic_refs_stop() {
+        let schema = "
+properties:
+  compatible:
+    const: v,dev
+$ref: \"#/definitions/node\"
+definitions:
+  node:
+    $ref: \"#/definitions/node\"
+    required: [ a ]
+    properties:
+      a:
+        $ref: \"#/definitions/prop\"
+  prop:
+    allOf:
+      - $ref: \"#/definitions/prop\"
+      - maxItems: 1
+";
+        assert!(violations(schema, " a = \"x\";").is_empty());
+        assert_eq!(
+            violations(schema, " a = \"x\", \"y\";"),
+            vec!["a has 2 items, more than 1"]
+        );
+        assert_eq!(violations(schema, ""), vec!["missing required property a"]);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::dt_value::{parse, strings, Cell, Value};
+use crate::root::{path_string, Root};
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Okay,
+    Disabled,
+    Reserved,
+    Fail(String),
+}
+
+pub fn own_status(root: &Root, path: &[String]) -> Status {
+    let status = root
+        .get_node(path)
+        .and_then(|n| n.property("status"))
+        .map(strings)
+        .and_then(|s| s.first().cloned());
+    match status.as_deref() {
+        None | Some("okay") | Some("ok") => Status::Okay,
+        Some("reserved") => Status::Reserved,
+        Some(s) if s.starts_with("fail") => Status::Fail(s.to_string()),
+        Some(_) => Status::Disabled,
+    }
+}
+
+// The status that applies to a node, and the node it comes from: a disabled
+// parent takes its whole subtree with it.
+pub fn effective(root: &Root, path: &[String]) -> (Status, Vec<String>) {
+    for i in 1..=path.len() {
+        let s = own_status(root, &path[..i]);
+        if s != Status::Okay {
+            return (s, path[..i].to_vec());
+        }
+    }
+    (Status::Okay, path.to_vec())
+}
+
+pub fn prune(root: &mut Root) {
+    let enabled: HashSet<Vec<String>> = root
+        .nodes()
+        .into_iter()
+        .filter(|(p, _)| effective(root, p).0 == Status::Okay)
+        .map(|(p, _)| p)
+        .collect();
+    root.retain(|p| enabled.contains(p));
+}
+
+pub struct Dependency {
+    pub node: Vec<String>,
+    pub property: String,
+    pub provider: Vec<String>,
+    pub status: Status,
+}
+
+pub fn disabled_dependencies(root: &Root) -> Vec<Dependency> {
+    let mut found = Vec::new();
+    for (path, node) in root.nodes() {
+        if effective(root, &path).0 != Status::Okay {
+            continue;
+        }
+        for (name, raw) in node.properties() {
+            let raw = match raw {
+                Some(r) => r,
+                None => continue,
+            };
+            for v in parse(raw) {
+                let cells = match v {
+                    Value::Cells(c) => c,
+                    _ => continue,
+                };
+                for c in cells.iter().filter(|c| matches!(c, Cell::Ref(_))) {
+                    let provider = match root.resolve_phandle(c) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    let (status, _) = effective(root, &provider);
+                    if status != Status::Okay && !path.starts_with(&provider) {
+                        found.push(Dependency {
+                            node: path.clone(),
+                            property: name.clone(),
+                            provider,
+                            status,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+fn describe(status: &Status) -> String {
+    match status {
+        Status::Okay => "okay".to_string(),
+        Status::Disabled => "disabled".to_string(),
+        Status::Reserved => "reserved".to_string(),
+        Status::Fail(s) => s.clone(),
+    }
+}
+
+pub fn print_status(root: &Root) {
+    for (path, node) in root.nodes() {
+        let (status, from) = effective(root, &path);
+        if status == Status::Okay {
+            continue;
+        }
+        if from == path {
+            let at = match node.property_location("status") {
+                Some(l) => format!(" ({})", l),
+                None => String::new(),
+            };
+            println!("{} {}{}", describe(&status), path_string(&path), at);
+        } else {
+            println!(
+                "{} {} (parent {})",
+                describe(&status),
+                path_string(&path),
+                path_string(&from)
+            );
+        }
+    }
+    for d in disabled_dependencies(root) {
+        let at = match root
+            .get_node(&d.node)
+            .and_then(|n| n.property_location(&d.property))
+        {
+            Some(l) => format!(" ({})", l),
+            None => String::new(),
+        };
+        println!(
+            "enabled {}{} depends on {} {} through {}",
+            path_string(&d.node),
+            at,
+            describe(&d.status),
+            path_string(&d.provider),
+            d.property
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use crate::dt_lexer::{lex, DTError, DTInfo, Span};
+use crate::dt_load::{resolve, LoadError};
+use crate::root::Location;
+use serde_json::{json, Value};
+use std::fs;
+
+pub struct Finding {
+    pub location: Location,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+// Instance-specific or vendor names and the generic name the kernel's DTS
+// coding style asks for instead.
+const GENERIC_NAMES: &[(&str, &str)] = &[
+    ("uart", "serial"),
+    ("usart", "serial"),
+    ("eth", "ethernet"),
+    ("emac", "ethernet"),
+    ("gmac", "ethernet"),
+    ("sdhci", "mmc"),
+    ("sdmmc", "mmc"),
+    ("ehci", "usb"),
+    ("ohci", "usb"),
+    ("xhci", "usb"),
+    ("wdt", "watchdog"),
+    ("wdog", "watchdog"),
+    ("gic", "interrupt-controller"),
+    ("intc", "interrupt-controller"),
+];
+
+#[derive(Default)]
+struct Block {
+    children: Vec<(String, Location)>,
+    properties: Vec<(String, Location)>,
+}
+
+struct Lint<'a> {
+    file: &'a str,
+    text: &'a str,
+    out: Vec<Finding>,
+}
+
+fn unit_address(name: &str) -> Option<&str> {
+    name.splitn(2, '@').nth(1)
+}
+
+fn address_value(unit: &str) -> Option<u128> {
+    let first = unit.split(',').next()?.trim_start_matches("0x");
+    u128::from_str_radix(first, 16).ok()
+}
+
+impl<'a> Lint<'a> {
+    fn location(&self, span: &Span) -> Location {
+        Location {
+            file: self.file.to_string(),
+            line: span.line,
+            column: span.column,
+        }
+    }
+
+    fn report(&mut self, location: Location, rule: &'static str, message: String) {
+        self.out.push(Finding {
+            location,
+            rule,
+            message,
+        });
+    }
+
+    // Everything on the token's line before it; None if the token does not
+    // start the line.
+    fn indent(&self, span: &Span) -> Option<&'a str> {
+        let line_start = span.start + 1 - span.column;
+        let prefix = &self.text[line_start..span.start];
+        if prefix.chars().all(|c| c == ' ' || c == '\t') {
+            Some(prefix)
+        } else {
+            None
+        }
+    }
+
+    fn check_indent(&mut self, span: &Span, depth: usize) {
+        let prefix = match self.indent(span) {
+            Some(p) => p,
+            None => return,
+        };
+        if prefix.contains(' ') {
+            self.report(
+                self.location(span),
+                "indent-tabs",
+                "indent with tabs, not spaces".to_string(),
+            );
+        } else if prefix.len() != depth {
+            self.report(
+                self.location(span),
+                "indent-depth",
+                format!("indented {} tabs, expected {}", prefix.len(), depth),
+            );
+        }
+    }
+
+    fn check_equals(&mut self, span: &Span, name: &str) {
+        let source = &self.text[span.start..span.end];
+        let rest = match source.strip_prefix(name) {
+            Some(r) => r,
+            None => return,
+        };
+        let eq = match rest.find('=') {
+            Some(e) => e,
+            None => return,
+        };
+        let after = &rest[eq + 1..];
+        let spaced = &rest[..eq] == " "
+            && after.starts_with(' ')
+            && !after[1..].starts_with(|c: char| c.is_whitespace());
+        if !spaced {
+            self.report(
+                self.location(span),
+                "spaces-around-equals",
+                format!("use a single space on each side of = in {}", name),
+            );
+        }
+    }
+
+    fn check_name(&mut self, span: &Span, name: &str) {
+        let loc = self.location(span);
+        let base = name.split('@').next().unwrap_or("");
+        if let Some(unit) = unit_address(name) {
+            for part in unit.split(',') {
+                if part.starts_with("0x") || part.starts_with("0X") {
+                    self.report(
+                        loc.clone(),
+                        "unit-address-format",
+                        format!("drop the 0x from unit address {}", unit),
+                    );
+                } else if part.len() > 1 && part.starts_with('0') {
+                    self.report(
+                        loc.clone(),
+                        "unit-address-format",
+                        format!("drop the leading zeros from unit address {}", unit),
+                    );
+                }
+            }
+            if unit.chars().any(|c| c.is_ascii_uppercase()) {
+                self.report(
+                    loc.clone(),
+                    "unit-address-format",
+                    format!("use lowercase hex in unit address {}", unit),
+                );
+            }
+        }
+        let stem = base.trim_end_matches(|c: char| c.is_ascii_digit());
+        let generic = GENERIC_NAMES
+            .iter()
+            .find(|(n, _)| *n == stem)
+            .map(|(_, g)| *g);
+        match generic {
+            Some(g) if g != base => self.report(
+                loc,
+                "generic-node-name",
+                format!("node name {} should be the generic {}", base, g),
+            ),
+            None if unit_address(name).is_some() && stem != base && !stem.is_empty() => self
+                .report(
+                    loc,
+                    "generic-node-name",
+                    format!("drop the instance number from node name {}", base),
+                ),
+            _ => (),
+        }
+    }
+
+    fn check_block(&mut self, block: &Block) {
+        if let Some((i, (_, loc))) = block
+            .properties
+            .iter()
+            .enumerate()
+            .find(|(_, (p, _))| p == "compatible")
+        {
+            if i != 0 {
+                self.report(
+                    loc.clone(),
+                    "property-order",
+                    "compatible should be the first property".to_string(),
+                );
+            }
+        }
+        if let Some((i, (_, loc))) = block
+            .properties
+            .iter()
+            .enumerate()
+            .find(|(_, (p, _))| p == "status")
+        {
+            if i != block.properties.len() - 1 {
+                self.report(
+                    loc.clone(),
+                    "property-order",
+                    "status should be the last property".to_string(),
+                );
+            }
+        }
+
+        // Nodes with a unit address go in address order, the rest by name.
+        let mut last_address: Option<(u128, &str)> = None;
+        let mut last_name: Option<&str> = None;
+        for (name, loc) in &block.children {
+            match unit_address(name).and_then(address_value) {
+                Some(a) => {
+                    if let Some((prev, prev_name)) = last_address {
+                        if a < prev {
+                            self.report(
+                                loc.clone(),
+                                "node-order",
+                                format!("{} should come before {}", name, prev_name),
+                            );
+                        }
+                    }
+                    last_address = Some((a, name));
+                }
+                None if unit_address(name).is_none() => {
+                    if let Some(prev) = last_name {
+                        if name.as_str() < prev {
+                            self.report(
+                                loc.clone(),
+                                "node-order",
+                                format!("{} should come before {}", name, prev),
+                            );
+                        }
+                    }
+                    last_name = Some(name);
+                }
+                None => (),
+            }
+        }
+    }
+
+    fn run(&mut self, tokens: &[(DTInfo, Span)]) {
+        // The file's top level is a block holding `&label { }` overrides.
+        let mut blocks: Vec<Block> = vec![Block::default()];
+        for (token, span) in tokens {
+            let depth = blocks.len() - 1;
+            match token {
+                DTInfo::Node(_, name) => {
+                    self.check_indent(span, depth);
+                    if name != "/" {
+                        self.check_name(span, name);
+                        let loc = self.location(span);
+                        if let Some(b) = blocks.last_mut() {
+                            b.children.push((name.clone(), loc));
+                        }
+                    }
+                    blocks.push(Block::default());
+                }
+                DTInfo::RefNode(label) => {
+                    self.check_indent(span, depth);
+                    let loc = self.location(span);
+                    if let Some(b) = blocks.last_mut() {
+                        b.children.push((format!("&{}", label), loc));
+                    }
+                    blocks.push(Block::default());
+                }
+                DTInfo::Property(name, value) => {
+                    self.check_indent(span, depth);
+                    if value.is_some() {
+                        self.check_equals(span, name);
+                    }
+                    let loc = self.location(span);
+                    if let Some(b) = blocks.last_mut() {
+                        b.properties.push((name.clone(), loc));
+                    }
+                }
+                DTInfo::NodeEnd => {
+                    if blocks.len() > 1 {
+                        let b = blocks.pop().unwrap_or_default();
+                        self.check_indent(span, blocks.len() - 1);
+                        self.check_block(&b);
+                    }
+                }
+                _ => (),
+            }
+        }
+        for b in blocks {
+            self.check_block(&b);
+        }
+    }
+}
+
+pub fn lint_tokens(file: &str, text: &str, tokens: &[(DTInfo, Span)]) -> Vec<Finding> {
+    let mut lint = Lint {
+        file,
+        text,
+        out: Vec::new(),
+    };
+    lint.run(tokens);
+    lint.out
+        .sort_by_key(|f| (f.location.line, f.location.column));
+    lint.out
+}
+
+// Lints a file and every .dts/.dtsi it includes, found as the loader finds
+// them; headers are left alone.
+pub fn lint_files(first_file: &str, dirs: &[String]) -> Vec<Finding> {
+    let read = |f: &str| fs::read_to_string(f).map_err(|e| e.to_string());
+    lint_sources(first_file, &read, dirs)
+}
+
+// A file that does not lex is reported where lexing stopped and the others
+// are still linted.
+fn lex_error(file: &str, text: &str, e: &DTError) -> Finding {
+    let before = text.get(..e.offset()).unwrap_or(text);
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    Finding {
+        location: Location {
+            file: file.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before.len() - start + 1,
+        },
+        rule: "syntax",
+        message: e.to_string(),
+    }
+}
+
+fn lint_sources<F: Fn(&str) -> Result<String, String>>(
+    first_file: &str,
+    read: &F,
+    dirs: &[String],
+) -> Vec<Finding> {
+    let mut found = Vec::new();
+    // The first file is read by its given path, includes relative to the
+    // file naming them.
+    let mut queue: Vec<(Option<String>, String)> = vec![(None, first_file.to_string())];
+    let mut seen: Vec<String> = Vec::new();
+    while let Some((from, name)) = queue.pop() {
+        let source = match &from {
+            Some(from) => resolve(from, &name, read, dirs).map_err(|e| e.to_string()),
+            None => read(&name)
+                .map(|t| (name.clone(), t))
+                .map_err(|e| LoadError::Read(name.clone(), e).to_string()),
+        };
+        let (file, text) = match source {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("warning: {}", e);
+                continue;
+            }
+        };
+        if seen.contains(&file) {
+            continue;
+        }
+        seen.push(file.clone());
+        let tokens = match lex(&text) {
+            Ok(t) => t,
+            Err(e) => {
+                found.push(lex_error(&file, &text, &e));
+                continue;
+            }
+        };
+        found.append(&mut lint_tokens(&file, &text, &tokens));
+        for (token, _) in tokens.into_iter().rev() {
+            if let DTInfo::Include(i) = token {
+                if i.ends_with(".dts") || i.ends_with(".dtsi") {
+                    queue.push((Some(file.clone()), i));
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn print_findings(findings: &[Finding]) {
+    for f in findings {
+        println!("{}: warning: {} [{}]", f.location, f.message, f.rule);
+    }
+}
+
+// One JSON array of {file, line, column, rule, message} for review bots.
+pub fn print_json(findings: &[Finding]) {
+    let entries: Vec<Value> = findings
+        .iter()
+        .map(|f| {
+            json!({
+                "file": f.location.file,
+                "line": f.location.line,
+                "column": f.location.column,
+                "rule": f.rule,
+                "message": f.message,
+            })
+        })
+        .collect();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint_sources;
+    use std::collections::HashMap;
+
+    fn lint(files: &[(&str, &str)], first: &str) -> Vec<String> {
+        let files: HashMap<String, String> = files
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        let read = |f: &str| files.get(f).cloned().ok_or_else(|| "missing".to_string());
+        lint_sources(first, &read, &[])
+            .into_iter()
+            .map(|f| format!("{} [{}]", f.location, f.rule))
+            .collect()
+    }
+
+    #[test]
+    fn lex_errors_do_not_stop_the_others() {
+        let files = [
+            (
+                "dir/a.dts",
+                "/dts-v1/;\n/include/ \"b.dtsi\"\n/include/ \"c.dtsi\"\n/ {\n};\n",
+            ),
+            ("dir/dir/a.dts", "/dts-v1/;\n/ {\n    x;\n};\n"),
+            ("dir/b.dtsi", "/ {\n\tx = <1> * ;\n};\n"),
+            ("dir/c.dtsi", "/ {\n  y;\n};\n"),
+        ];
+        assert_eq!(
+            lint(&files, "dir/a.dts"),
+            vec!["dir/b.dtsi:2:10 [syntax]", "dir/c.dtsi:2:3 [indent-tabs]"]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Num(u32),
+    Ref(String),
+    Symbol(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Cells(Vec<Cell>),
+    Str(String),
+    Ref(String),
+}
+
+pub fn parse_num(s: &str) -> Option<u32> {
+    parse_u64(s).and_then(|n| u32::try_from(n).ok())
+}
+
+// Like parse_num, for /memreserve/ and other 64-bit numbers.
+pub fn parse_u64(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let s = s.trim_end_matches(|c| c == 'u' || c == 'U' || c == 'l' || c == 'L');
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else if s.len() > 1 && s.starts_with('0') {
+        u64::from_str_radix(&s[1..], 8).ok()
+    } else {
+        s.parse::<u64>().ok()
+    }
+}
+
+// Property values are stored as the lexer's tokens joined by spaces, so
+// `reg = <0x1000 0x100>, "a b";` arrives here as `< 0x1000 0x100 > , " a b "`.
+pub fn parse(raw: &str) -> Vec<Value> {
+    let mut values: Vec<Value> = Vec::new();
+    let mut cells: Option<Vec<Cell>> = None;
+    let mut string: Option<Vec<&str>> = None;
+
+    for word in raw.split_whitespace() {
+        if let Some(s) = string.as_mut() {
+            if word == "\"" {
+                values.push(Value::Str(s.join(" ")));
+                string = None;
+            } else {
+                s.push(word);
+            }
+            continue;
+        }
+        if let Some(c) = cells.as_mut() {
+            if word == ">" {
+                values.push(Value::Cells(cells.take().unwrap()));
+            } else if word.starts_with('&') {
+                c.push(Cell::Ref(word[1..].to_string()));
+            } else {
+                match parse_num(word) {
+                    Some(n) => c.push(Cell::Num(n)),
+                    None => c.push(Cell::Symbol(word.to_string())),
+                }
+            }
+            continue;
+        }
+        match word {
+            "\"" => string = Some(Vec::new()),
+            "<" => cells = Some(Vec::new()),
+            "," => (),
+            _ => {
+                if word.starts_with('&') {
+                    values.push(Value::Ref(word[1..].to_string()));
+                }
+            }
+        }
+    }
+    values
+}
+
+pub fn strings(raw: &Option<String>) -> Vec<String> {
+    match raw {
+        Some(r) => parse(r)
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::Str(s) => Some(s),
+                _ => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Puts a raw value back into DTS spelling: `< 0x1 0x2 > , " a b "` becomes
+// `<0x1 0x2>, "a b"`.
+pub fn render(raw: &str) -> String {
+    let mut out = String::new();
+    let mut in_string = false;
+    let mut open = true;
+    for word in raw.split_whitespace() {
+        let closing = match word {
+            ">" | "]" | "," => true,
+            "\"" => in_string,
+            _ => false,
+        };
+        if !open && !closing {
+            out.push(' ');
+        }
+        out.push_str(word);
+        open = match word {
+            "<" | "[" => true,
+            "\"" => !in_string,
+            _ => false,
+        };
+        if word == "\"" {
+            in_string = !in_string;
+        }
+    }
+    out
+}
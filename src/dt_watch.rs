@@ -0,0 +1,46 @@
+use crate::dt_load::{self, Cache, Options, Source};
+use crate::root::Root;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL: Duration = Duration::from_millis(500);
+
+fn modified(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Hands the tree to `render` now and again whenever the input or one of the
+// files it includes changes. Errors are printed and waited out.
+pub fn watch<F: FnMut(&[Source], Root)>(first_file: &str, options: &Options, mut render: F) -> ! {
+    let read = |f: &str| fs::read_to_string(f).map_err(|e| e.to_string());
+    let mut cache = Cache::new();
+    let mut files = vec![first_file.to_string()];
+    let terminal = io::stdout().is_terminal();
+    loop {
+        if terminal {
+            print!("\x1b[2J\x1b[H");
+        }
+        match cache.sources(first_file, &read, options) {
+            Ok(sources) => {
+                files = sources.iter().map(|s| s.file.clone()).collect();
+                match dt_load::build_with(&sources, first_file, options) {
+                    Ok(r) => render(&sources, r),
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            // Keep watching the files of the last good load, one of which
+            // holds the mistake.
+            Err(e) => eprintln!("error: {}", e),
+        }
+        let _ = io::stdout().flush();
+        let seen = modified(&files);
+        while modified(&files) == seen {
+            thread::sleep(POLL);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::dt_value::{parse, render, Cell, Value};
+use crate::root::{path_string, DTNode, Root};
+use serde_json::{json, Map};
+
+// Swaps macros for their values, so the output stands without its headers.
+pub(crate) fn expand_macros(root: &Root, raw: &str) -> String {
+    let mut in_string = false;
+    let words: Vec<String> = raw
+        .split_whitespace()
+        .map(|w| {
+            if w == "\"" {
+                in_string = !in_string;
+            }
+            match root.define(w).and_then(|_| root.expand(w)) {
+                Some(n) if !in_string => n.to_string(),
+                _ => w.to_string(),
+            }
+        })
+        .collect();
+    words.join(" ")
+}
+
+fn write_node(root: &Root, out: &mut String, path: &mut Vec<String>, depth: usize, node: &DTNode) {
+    let indent = "\t".repeat(depth);
+    let mut labels = String::new();
+    for l in root.labels_for(path) {
+        labels.push_str(&format!("{}: ", l));
+    }
+    out.push_str(&format!("{}{}{} {{\n", indent, labels, node.name));
+    let properties = node.properties();
+    for (name, value) in &properties {
+        match value {
+            Some(v) => out.push_str(&format!(
+                "{}\t{} = {};\n",
+                indent,
+                name,
+                render(&expand_macros(root, v))
+            )),
+            None => out.push_str(&format!("{}\t{};\n", indent, name)),
+        }
+    }
+    for (i, c) in node.children().into_iter().enumerate() {
+        if i > 0 || !properties.is_empty() {
+            out.push('\n');
+        }
+        path.push(c.name.clone());
+        write_node(root, out, path, depth + 1, c);
+        path.pop();
+    }
+    out.push_str(&format!("{}}};\n", indent));
+}
+
+// The merged tree as a single DTS file dtc can compile.
+pub fn dts(root: &Root) -> String {
+    let mut out = String::from("/dts-v1/;\n\n");
+    for (address, size, _) in root.memreserve() {
+        out.push_str(&format!("/memreserve/ {:#x} {:#x};\n", address, size));
+    }
+    if !root.memreserve().is_empty() {
+        out.push('\n');
+    }
+    if let Some(n) = root.get_node(&["/"]) {
+        write_node(root, &mut out, &mut vec!["/".to_string()], 0, n);
+    }
+    out
+}
+
+// One node and everything below it, unindented, as it would appear in `dts`.
+pub fn dts_node(root: &Root, path: &[String]) -> Option<String> {
+    let mut out = String::new();
+    write_node(root, &mut out, &mut path.to_vec(), 0, root.get_node(path)?);
+    Some(out)
+}
+
+fn json_value(root: &Root, raw: &Option<String>) -> serde_json::Value {
+    let raw = match raw {
+        Some(r) => r,
+        None => return json!(true),
+    };
+    let values: Vec<serde_json::Value> = parse(raw)
+        .into_iter()
+        .map(|v| match v {
+            Value::Cells(cells) => cells
+                .into_iter()
+                .map(|c| match c {
+                    Cell::Num(n) => json!(n),
+                    Cell::Ref(l) => json!(format!("&{}", l)),
+                    Cell::Symbol(s) => match root.expand(&s) {
+                        Some(n) => json!(n),
+                        None => json!(s),
+                    },
+                })
+                .collect(),
+            Value::Str(s) => json!(s),
+            Value::Ref(l) => json!(format!("&{}", l)),
+        })
+        .collect();
+    serde_json::Value::Array(values)
+}
+
+fn json_node(root: &Root, path: &mut Vec<String>, node: &DTNode) -> serde_json::Value {
+    let mut properties = Map::new();
+    for (name, value) in node.properties() {
+        properties.insert(name.clone(), json_value(root, value));
+    }
+    let mut children = Vec::new();
+    for c in node.children() {
+        path.push(c.name.clone());
+        children.push(json_node(root, path, c));
+        path.pop();
+    }
+    json!({
+        "path": path_string(path),
+        "name": node.name,
+        "labels": root.labels_for(path),
+        "properties": properties,
+        "children": children,
+    })
+}
+
+// The merged tree as JSON: each node has its path, name, labels, properties
+// and children. A property is `true` when it has no value and otherwise a
+// list of its comma-separated parts: strings, `&label` references, and cell
+// lists with macros expanded.
+pub fn json(root: &Root) -> serde_json::Value {
+    match root.get_node(&["/"]) {
+        Some(n) => json_node(root, &mut vec!["/".to_string()], n),
+        None => serde_json::Value::Null,
+    }
+}
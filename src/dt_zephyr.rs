@@ -0,0 +1,509 @@
+use crate::dt_schema::{SchemaError, Violation};
+use crate::dt_value::{parse, render, strings, Cell, Value};
+use crate::root::{location, path_string, Root};
+use std::fs;
+use std::path::Path;
+use yaml_rust::{Yaml, YamlLoader};
+
+pub struct Binding {
+    pub file: String,
+    pub compatible: String,
+    pub doc: Yaml,
+}
+
+pub struct Property {
+    pub name: String,
+    pub value: Option<String>,
+    pub default: bool,
+}
+
+pub struct Effective {
+    pub path: Vec<String>,
+    pub binding: String,
+    pub properties: Vec<Property>,
+}
+
+const ALWAYS_KNOWN: &[&str] = &[
+    "compatible",
+    "status",
+    "phandle",
+    "linux,phandle",
+    "#address-cells",
+    "#size-cells",
+    "pinctrl-names",
+];
+
+fn key(s: &str) -> Yaml {
+    Yaml::String(s.to_string())
+}
+
+fn read_dir(dir: &Path, found: &mut Vec<(String, Yaml)>) -> Result<(), SchemaError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => return Err(SchemaError::Io(dir.display().to_string(), e)),
+    };
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+    for p in paths {
+        if p.is_dir() {
+            read_dir(&p, found)?;
+        } else if p.extension().map_or(false, |e| e == "yaml") {
+            let name = p.display().to_string();
+            let text = match fs::read_to_string(&p) {
+                Ok(t) => t,
+                Err(e) => return Err(SchemaError::Io(name, e)),
+            };
+            match YamlLoader::load_from_str(&text) {
+                Ok(mut d) if !d.is_empty() => found.push((name, d.remove(0))),
+                Ok(_) => (),
+                Err(e) => return Err(SchemaError::Yaml(name, e)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// The including binding wins, except that `required: true` cannot be undone.
+fn merge(into: &mut Yaml, from: &Yaml) {
+    if let (Yaml::Hash(a), Yaml::Hash(b)) = (into, from) {
+        for (k, v) in b {
+            match a.get_mut(k) {
+                Some(existing) => {
+                    if k.as_str() == Some("required") && *v == Yaml::Boolean(true) {
+                        *existing = v.clone();
+                    } else {
+                        merge(existing, v);
+                    }
+                }
+                None => {
+                    a.insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+}
+
+fn names(y: &Yaml) -> Vec<&str> {
+    match y {
+        Yaml::Array(a) => a.iter().filter_map(|s| s.as_str()).collect(),
+        Yaml::String(s) => vec![s.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+// Applies an include entry's property-allowlist/-blocklist, also to its
+// child-binding.
+fn filter_properties(doc: &mut Yaml, spec: &Yaml) {
+    let allow = match &spec["property-allowlist"] {
+        Yaml::BadValue => None,
+        y => Some(names(y)),
+    };
+    let block = names(&spec["property-blocklist"]);
+    if let Yaml::Hash(h) = doc {
+        if let Some(Yaml::Hash(props)) = h.get_mut(&key("properties")) {
+            let dropped: Vec<Yaml> = props
+                .keys()
+                .filter(|k| {
+                    let n = k.as_str().unwrap_or("");
+                    allow.as_ref().map_or(false, |a| !a.contains(&n)) || block.contains(&n)
+                })
+                .cloned()
+                .collect();
+            for k in dropped {
+                props.remove(&k);
+            }
+        }
+        if !spec["child-binding"].is_badvalue() {
+            if let Some(child) = h.get_mut(&key("child-binding")) {
+                filter_properties(child, &spec["child-binding"]);
+            }
+        }
+    }
+}
+
+// Zephyr includes name a file anywhere in the binding directories.
+fn expand(files: &[(String, Yaml)], name: &str, doc: &Yaml, depth: usize) -> Yaml {
+    let mut out = doc.clone();
+    if let Yaml::Hash(h) = &mut out {
+        h.remove(&key("include"));
+    }
+    if depth > 16 {
+        eprintln!("warning: {}: includes nested too deep", name);
+        return out;
+    }
+    let includes: Vec<&Yaml> = match &doc["include"] {
+        Yaml::Array(a) => a.iter().collect(),
+        Yaml::BadValue => Vec::new(),
+        y => vec![y],
+    };
+    for inc in includes {
+        let file = inc.as_str().or_else(|| inc["name"].as_str()).unwrap_or("");
+        let found = files
+            .iter()
+            .find(|(f, _)| Path::new(f).file_name().map_or(false, |n| n == file));
+        match found {
+            Some((f, d)) => {
+                let mut included = expand(files, f, d, depth + 1);
+                filter_properties(&mut included, inc);
+                merge(&mut out, &included);
+            }
+            None => eprintln!("warning: {}: included binding {} not found", name, file),
+        }
+    }
+    if !doc["child-binding"]["include"].is_badvalue() {
+        let child = expand(files, name, &doc["child-binding"], depth + 1);
+        if let Yaml::Hash(h) = &mut out {
+            h.insert(key("child-binding"), child);
+        }
+    }
+    out
+}
+
+pub fn load_dirs(dirs: &[String]) -> Result<Vec<Binding>, SchemaError> {
+    let mut files = Vec::new();
+    for d in dirs {
+        read_dir(Path::new(d), &mut files)?;
+    }
+    Ok(files
+        .iter()
+        .filter_map(|(f, d)| {
+            d["compatible"].as_str().map(|c| Binding {
+                file: f.clone(),
+                compatible: c.to_string(),
+                doc: expand(&files, f, d, 0),
+            })
+        })
+        .collect())
+}
+
+// The binding describing a node: the first of its compatibles with a binding,
+// preferring one whose on-bus matches the parent's bus, or else the parent's
+// child-binding.
+fn binding_for<'a>(
+    root: &Root,
+    bindings: &'a [Binding],
+    path: &[String],
+) -> Option<(&'a Binding, &'a Yaml)> {
+    let node = root.get_node(path)?;
+    let parent = if path.len() > 1 {
+        binding_for(root, bindings, &path[..path.len() - 1])
+    } else {
+        None
+    };
+    let bus = parent.map(|(_, y)| names(&y["bus"])).unwrap_or_default();
+    let compatible = node.property("compatible").map(strings).unwrap_or_default();
+    for c in &compatible {
+        let candidates: Vec<&Binding> = bindings.iter().filter(|b| b.compatible == *c).collect();
+        let on_bus = candidates
+            .iter()
+            .find(|b| b.doc["on-bus"].as_str().map_or(false, |o| bus.contains(&o)));
+        let plain = candidates.iter().find(|b| b.doc["on-bus"].is_badvalue());
+        if let Some(b) = on_bus.or(plain) {
+            return Some((b, &b.doc));
+        }
+    }
+    match parent {
+        Some((b, y)) if !y["child-binding"].is_badvalue() => Some((b, &y["child-binding"])),
+        _ => None,
+    }
+}
+
+fn type_ok(t: &str, raw: &Option<String>) -> bool {
+    let raw = match raw {
+        Some(r) => r,
+        None => return t == "boolean" || t == "compound",
+    };
+    let values = parse(raw);
+    let cells: Vec<&Cell> = values
+        .iter()
+        .filter_map(|v| match v {
+            Value::Cells(c) => Some(c),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    let groups = values
+        .iter()
+        .filter(|v| matches!(v, Value::Cells(_)))
+        .count();
+    let strings = values.iter().filter(|v| matches!(v, Value::Str(_))).count();
+    let phandle = |c: &&Cell| matches!(c, Cell::Ref(_) | Cell::Num(_));
+    match t {
+        "boolean" => false,
+        "string" => values.len() == 1 && strings == 1,
+        "string-array" => !values.is_empty() && strings == values.len(),
+        "int" => groups == 1 && values.len() == 1 && cells.len() == 1,
+        "array" => groups == values.len(),
+        "uint8-array" => raw.contains('['),
+        "phandle" => groups == 1 && cells.len() == 1 && cells.iter().all(phandle),
+        "phandles" => groups == values.len() && !cells.is_empty() && cells.iter().all(phandle),
+        "phandle-array" => groups == values.len() && cells.first().map_or(false, phandle),
+        "path" => values.len() == 1 && matches!(values[0], Value::Str(_) | Value::Ref(_)),
+        _ => true,
+    }
+}
+
+fn items(root: &Root, raw: &Option<String>) -> Vec<Yaml> {
+    let mut found: Vec<Yaml> = strings(raw).into_iter().map(Yaml::String).collect();
+    found.extend(root.cells(raw).into_iter().map(|c| match c {
+        Cell::Num(n) => Yaml::Integer(n as i64),
+        _ => Yaml::Null,
+    }));
+    found
+}
+
+fn default_value(t: &str, y: &Yaml) -> Option<String> {
+    let ints = |y: &Yaml| -> Vec<String> {
+        match y {
+            Yaml::Array(a) => a
+                .iter()
+                .filter_map(|i| i.as_i64())
+                .map(|i| i.to_string())
+                .collect(),
+            y => y.as_i64().map(|i| i.to_string()).into_iter().collect(),
+        }
+    };
+    let quoted = |s: &str| format!("\" {} \"", s);
+    match t {
+        "int" | "array" => Some(format!("< {} >", ints(y).join(" "))),
+        "uint8-array" => {
+            let bytes: Vec<String> = ints(y)
+                .iter()
+                .filter_map(|i| i.parse::<u8>().ok())
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            Some(format!("[ {} ]", bytes.join(" ")))
+        }
+        "string" => y.as_str().map(quoted),
+        "string-array" => Some(
+            names(y)
+                .into_iter()
+                .map(quoted)
+                .collect::<Vec<_>>()
+                .join(" , "),
+        ),
+        _ => None,
+    }
+}
+
+fn known(binding: &Yaml, name: &str) -> bool {
+    let pinctrl = name
+        .strip_prefix("pinctrl-")
+        .map_or(false, |n| n.parse::<u32>().is_ok());
+    ALWAYS_KNOWN.contains(&name) || pinctrl || !binding["properties"][name].is_badvalue()
+}
+
+pub fn validate(root: &Root, bindings: &[Binding]) -> Vec<Violation> {
+    let mut out = Vec::new();
+    for (path, node) in root.nodes() {
+        let (binding, y) = match binding_for(root, bindings, &path) {
+            Some(b) => b,
+            None => continue,
+        };
+        let mut report = |property: Option<&str>, message: String| {
+            out.push(Violation {
+                path: path.clone(),
+                location: location(root, &path, property),
+                message,
+                schema: binding.file.clone(),
+            })
+        };
+        if let Yaml::Hash(props) = &y["properties"] {
+            for (k, spec) in props {
+                let name = match k.as_str() {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let raw = match node.property(name) {
+                    Some(r) => r,
+                    None => {
+                        if spec["required"] == Yaml::Boolean(true) {
+                            report(None, format!("missing required property {}", name));
+                        }
+                        continue;
+                    }
+                };
+                if spec["deprecated"] == Yaml::Boolean(true) {
+                    report(Some(name), format!("{} is deprecated", name));
+                }
+                if let Some(t) = spec["type"].as_str() {
+                    if !type_ok(t, raw) {
+                        report(Some(name), format!("{} is not of type {}", name, t));
+                        continue;
+                    }
+                }
+                let values = items(root, raw);
+                if let Yaml::Array(allowed) = &spec["enum"] {
+                    if let Some(v) = values.iter().find(|v| !allowed.contains(v)) {
+                        report(
+                            Some(name),
+                            format!("{} value {:?} is not one of {:?}", name, v, allowed),
+                        );
+                    }
+                }
+                if !spec["const"].is_badvalue() && values.first() != Some(&spec["const"]) {
+                    report(Some(name), format!("{} must be {:?}", name, spec["const"]));
+                }
+            }
+        }
+        for (name, _) in node.properties() {
+            if !known(y, name) {
+                report(Some(name), format!("unknown property {}", name));
+            }
+        }
+    }
+    out
+}
+
+pub fn effective_properties(root: &Root, bindings: &[Binding]) -> Vec<Effective> {
+    let mut found = Vec::new();
+    for (path, node) in root.nodes() {
+        let (binding, y) = match binding_for(root, bindings, &path) {
+            Some(b) => b,
+            None => continue,
+        };
+        let mut properties: Vec<Property> = node
+            .properties()
+            .into_iter()
+            .map(|(n, v)| Property {
+                name: n.clone(),
+                value: v.clone(),
+                default: false,
+            })
+            .collect();
+        if let Yaml::Hash(props) = &y["properties"] {
+            for (k, spec) in props {
+                let name = match k.as_str() {
+                    Some(n) => n,
+                    None => continue,
+                };
+                if node.has_property(name) || spec["default"].is_badvalue() {
+                    continue;
+                }
+                let t = spec["type"].as_str().unwrap_or("");
+                match default_value(t, &spec["default"]) {
+                    Some(v) => properties.push(Property {
+                        name: name.to_string(),
+                        value: Some(v),
+                        default: true,
+                    }),
+                    None => eprintln!(
+                        "warning: {}: default for {} of type {} ignored",
+                        binding.file, name, t
+                    ),
+                }
+            }
+        }
+        properties.sort_by(|a, b| a.name.cmp(&b.name));
+        found.push(Effective {
+            path,
+            binding: binding.file.clone(),
+            properties,
+        });
+    }
+    found
+}
+
+pub fn print_properties(effective: &[Effective]) {
+    for e in effective {
+        println!("{} [{}]", path_string(&e.path), e.binding);
+        for p in &e.properties {
+            let value = match &p.value {
+                Some(v) => format!(" = {}", render(v)),
+                None => String::new(),
+            };
+            let default = if p.default { " (default)" } else { "" };
+            println!("    {}{};{}", p.name, value, default);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{effective_properties, expand, Binding};
+    use crate::root::path_string;
+    use crate::{load_str, Options};
+    use yaml_rust::{Yaml, YamlLoader};
+
+    fn yaml(s: &str) -> Yaml {
+        YamlLoader::load_from_str(s).unwrap().remove(0)
+    }
+
+    const BASE: &str = "
+properties:
+  speed:
+    type: int
+    default: 100
+";
+
+    const DEV: &str = "
+compatible: v,dev
+include: base.yaml
+properties:
+  mode:
+    type: string
+    default: fast
+  names:
+    type: string-array
+    default: [ a, b ]
+  taps:
+    type: array
+    default: [ 1, 2 ]
+  mac:
+    type: uint8-array
+    default: [ 0, 255 ]
+  weight:
+    type: int
+    default: 7
+  ref:
+    type: phandle
+    default: 1
+child-binding:
+  properties:
+    level:
+      type: int
+      default: 3
+";
+
+    #[test]
+    fn defaults_fill_missing_properties() {
+        let files = vec![
+            ("base.yaml".to_string(), yaml(BASE)),
+            ("dev.yaml".to_string(), yaml(DEV)),
+        ];
+        let bindings = vec![Binding {
+            file: "dev.yaml".to_string(),
+            compatible: "v,dev".to_string(),
+            doc: expand(&files, "dev.yaml", &files[1].1, 0),
+        }];
+        let dts = "/dts-v1/;\n/ {\n\tdev { compatible = \"v,dev\"; weight = <9>;\n\
+                   \t\tchild { level = <4>; };\n\t\tother { };\n\t};\n};\n";
+        let root = load_str("a.dts", dts, &Options::default()).unwrap();
+        let mut found = Vec::new();
+        for e in effective_properties(&root, &bindings) {
+            for p in e.properties {
+                found.push(format!(
+                    "{} {} = {}{}",
+                    path_string(&e.path),
+                    p.name,
+                    p.value.unwrap_or_default(),
+                    if p.default { " (default)" } else { "" }
+                ));
+            }
+        }
+        assert_eq!(
+            found,
+            vec![
+                "/dev compatible = \" v,dev \"",
+                "/dev mac = [ 00 ff ] (default)",
+                "/dev mode = \" fast \" (default)",
+                "/dev names = \" a \" , \" b \" (default)",
+                "/dev speed = < 100 > (default)",
+                "/dev taps = < 1 2 > (default)",
+                "/dev weight = < 9 >",
+                "/dev/child level = < 4 >",
+                "/dev/other level = < 3 > (default)",
+            ]
+        );
+    }
+}
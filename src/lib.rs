@@ -0,0 +1,94 @@
+#![cfg_attr(test, feature(test))]
+
+// The modules behind the mimir CLI, public only for the binary and left out
+// of the docs. Other tools should use the items re-exported below, which are
+// kept stable; the modules change with the CLI.
+#[doc(hidden)]
+pub mod dt_address;
+#[doc(hidden)]
+pub mod dt_check;
+#[doc(hidden)]
+pub mod dt_clock;
+#[doc(hidden)]
+pub mod dt_dot;
+#[doc(hidden)]
+pub mod dt_fmt;
+#[doc(hidden)]
+pub mod dt_gpio;
+#[doc(hidden)]
+pub mod dt_header;
+#[doc(hidden)]
+pub mod dt_include;
+#[doc(hidden)]
+pub mod dt_irq;
+#[doc(hidden)]
+pub mod dt_lexer;
+#[doc(hidden)]
+pub mod dt_load;
+#[doc(hidden)]
+pub mod dt_lsp;
+#[doc(hidden)]
+pub mod dt_overlap;
+#[doc(hidden)]
+pub mod dt_overlay;
+#[doc(hidden)]
+pub mod dt_patch;
+#[doc(hidden)]
+pub mod dt_phandle;
+#[doc(hidden)]
+pub mod dt_pinctrl;
+#[doc(hidden)]
+pub mod dt_schema;
+#[doc(hidden)]
+pub mod dt_status;
+#[doc(hidden)]
+pub mod dt_style;
+#[doc(hidden)]
+pub mod dt_value;
+#[doc(hidden)]
+pub mod dt_watch;
+#[doc(hidden)]
+pub mod dt_write;
+#[doc(hidden)]
+pub mod dt_zephyr;
+#[doc(hidden)]
+pub mod root;
+
+pub use dt_address::{decode_reg, AddressError, Region};
+pub use dt_header::header;
+pub use dt_load::{LoadError, Options, Source};
+pub use dt_overlay::overlay;
+pub use dt_patch::{patch, Patch};
+pub use dt_value::{Cell, Value};
+pub use dt_write::{dts, dts_node, json};
+pub use root::{path_string, DTNode, Location, NodeRef, Root, RootError};
+
+use std::fs;
+
+// Loads a tree from a file and everything it includes.
+pub fn load_file(path: &str, options: &Options) -> Result<Root, LoadError> {
+    let read = |f: &str| fs::read_to_string(f).map_err(|e| e.to_string());
+    dt_load::load(path, &read, options)
+}
+
+// Like `load_file`, also giving the files read, which `patch` needs to
+// write changes back to the first.
+pub fn load_sources(path: &str, options: &Options) -> Result<(Vec<Source>, Root), LoadError> {
+    let read = |f: &str| fs::read_to_string(f).map_err(|e| e.to_string());
+    let sources = dt_load::sources(path, &read, options)?;
+    let root = dt_load::build_with(&sources, path, options)?;
+    Ok((sources, root))
+}
+
+// Loads a tree from DTS text; `name` stands for its file in locations and
+// when resolving includes, which are read from disk.
+pub fn load_str(name: &str, text: &str, options: &Options) -> Result<Root, LoadError> {
+    let read = |f: &str| {
+        if f == name {
+            Ok(text.to_string())
+        } else {
+            fs::read_to_string(f).map_err(|e| e.to_string())
+        }
+    };
+    dt_load::load(name, &read, options)
+}
@@ -3,20 +3,27 @@
 #[macro_use]
 extern crate structopt;
 
-mod dt_lexer;
-mod root;
-
-use dt_lexer::{lex, DTInfo};
-use root::Root;
-use std::fs::File;
-use std::io::Read;
+use mimir::dt_load::{self, Source};
+use mimir::root::Root;
+use mimir::{
+    dt_address, dt_check, dt_clock, dt_dot, dt_fmt, dt_gpio, dt_header, dt_include, dt_irq, dt_lsp,
+    dt_overlap, dt_overlay, dt_pinctrl, dt_schema, dt_status, dt_style, dt_watch, dt_write,
+    dt_zephyr,
+};
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::str;
+use structopt::clap;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 enum OutputFormat {
     File,
     DotFile,
+    Dts,
+    Json,
+    Header,
 }
 
 impl str::FromStr for OutputFormat {
@@ -27,19 +34,67 @@ impl str::FromStr for OutputFormat {
             "file" => Ok(Self::File),
             "dot" => Ok(Self::DotFile),
             "dotfile" => Ok(Self::DotFile),
+            "dts" => Ok(Self::Dts),
+            "json" => Ok(Self::Json),
+            "header" => Ok(Self::Header),
+            "h" => Ok(Self::Header),
             _ => Err("Invalid output format".to_string()),
         }
     }
 }
 
+#[derive(StructOpt, Debug)]
+enum Report {
+    Reg,
+    MemoryMap,
+    Translate,
+    Overlaps,
+    Interrupts,
+    IrqConflicts,
+    Clocks,
+    Gpio,
+    Pinctrl,
+    Status,
+    Checks,
+    Includes,
+}
+
+impl str::FromStr for Report {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reg" => Ok(Self::Reg),
+            "memory-map" => Ok(Self::MemoryMap),
+            "memmap" => Ok(Self::MemoryMap),
+            "translate" => Ok(Self::Translate),
+            "overlaps" => Ok(Self::Overlaps),
+            "interrupts" => Ok(Self::Interrupts),
+            "irq" => Ok(Self::Interrupts),
+            "irq-conflicts" => Ok(Self::IrqConflicts),
+            "clocks" => Ok(Self::Clocks),
+            "gpio" => Ok(Self::Gpio),
+            "gpios" => Ok(Self::Gpio),
+            "pinctrl" => Ok(Self::Pinctrl),
+            "status" => Ok(Self::Status),
+            "checks" => Ok(Self::Checks),
+            "includes" => Ok(Self::Includes),
+            _ => Err("Invalid report".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "Mimir",
     about = "Pull together information from a device tree file and its includes"
 )]
 struct Opt {
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
     #[structopt(help = "Path to device tree file")]
-    input: String,
+    input: Option<String>,
 
     #[structopt(
         short = "o",
@@ -48,115 +103,363 @@ struct Opt {
         default_value = "File"
     )]
     output: OutputFormat,
+
+    #[structopt(
+        short = "r",
+        long = "report",
+        number_of_values = 1,
+        help = "Report to print instead of the tree, may be repeated (reg, memory-map, translate, overlaps, interrupts, irq-conflicts, clocks, gpio, pinctrl, status, checks, includes)"
+    )]
+    report: Vec<Report>,
+
+    #[structopt(
+        long = "effective",
+        help = "Drop disabled, reserved and failed nodes before any output or report"
+    )]
+    effective: bool,
+
+    #[structopt(
+        short = "W",
+        number_of_values = 1,
+        help = "Enable (NAME) or disable (no-NAME) a check as a warning, may be repeated"
+    )]
+    warning: Vec<dt_check::Toggle>,
+
+    #[structopt(
+        short = "E",
+        number_of_values = 1,
+        help = "Enable (NAME) or disable (no-NAME) a check as an error, may be repeated"
+    )]
+    error: Vec<dt_check::Toggle>,
+
+    #[structopt(
+        long = "watch",
+        help = "Print again whenever the input or a file it includes changes"
+    )]
+    watch: bool,
+
+    #[structopt(
+        short = "d",
+        long = "depfile",
+        help = "Write a Make/Ninja rule listing the input and its includes to this file"
+    )]
+    depfile: Option<String>,
+
+    #[structopt(
+        long = "dep-target",
+        help = "Target of the depfile rule, by default the input with a .dtb extension"
+    )]
+    dep_target: Option<String>,
+
+    #[structopt(
+        long = "include-once",
+        help = "Skip files already included, as if each had an include guard"
+    )]
+    include_once: bool,
+
+    #[structopt(
+        short = "i",
+        long = "include",
+        number_of_values = 1,
+        help = "Directory to search for includes, may be repeated"
+    )]
+    include_dirs: Vec<String>,
+
+    #[structopt(
+        short = "D",
+        number_of_values = 1,
+        help = "Define a macro as NAME or NAME=VALUE before reading, may be repeated"
+    )]
+    defines: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(
+        name = "validate",
+        about = "Check the tree against dt-schema YAML bindings"
+    )]
+    Validate {
+        #[structopt(
+            short = "s",
+            long = "schemas",
+            number_of_values = 1,
+            help = "Directory of binding schemas, searched recursively, may be repeated"
+        )]
+        schemas: Vec<String>,
+
+        #[structopt(help = "Path to device tree file")]
+        input: String,
+    },
+    #[structopt(name = "fmt", about = "Reformat .dts/.dtsi files in place")]
+    Fmt {
+        #[structopt(
+            long = "check",
+            help = "Only report files that would change, exiting non-zero if any"
+        )]
+        check: bool,
+
+        #[structopt(required = true, help = "Device tree files to format")]
+        files: Vec<String>,
+    },
+    #[structopt(
+        name = "lint",
+        about = "Check the source and its .dtsi includes against the kernel DTS coding style"
+    )]
+    Lint {
+        #[structopt(long = "json", help = "Print findings as a JSON array")]
+        json: bool,
+
+        #[structopt(help = "Path to device tree file")]
+        input: String,
+    },
+    #[structopt(
+        name = "zephyr",
+        about = "Check the tree against Zephyr bindings and apply their defaults"
+    )]
+    Zephyr {
+        #[structopt(
+            short = "b",
+            long = "bindings",
+            number_of_values = 1,
+            help = "Zephyr binding directory, searched recursively, may be repeated"
+        )]
+        bindings: Vec<String>,
+
+        #[structopt(
+            long = "properties",
+            help = "Print each bound node's properties with binding defaults filled in"
+        )]
+        properties: bool,
+
+        #[structopt(help = "Path to device tree file")]
+        input: String,
+    },
+    #[structopt(
+        name = "overlay",
+        about = "Print an overlay that turns the base tree into the modified one"
+    )]
+    Overlay {
+        #[structopt(help = "Path to the device tree the overlay applies to")]
+        base: String,
+
+        #[structopt(help = "Path to the device tree with the changes")]
+        modified: String,
+    },
+    #[structopt(
+        name = "lsp",
+        about = "Serve definitions, hover, symbols and diagnostics over stdio"
+    )]
+    Lsp,
+}
+
+fn load_sources(first_file: &str, options: &dt_load::Options) -> Vec<Source> {
+    let read = |f: &str| fs::read_to_string(f).map_err(|e| e.to_string());
+    match dt_load::sources(first_file, &read, options) {
+        Ok(s) => s,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+fn load(first_file: &str, options: &dt_load::Options) -> Root {
+    let r = match mimir::load_file(first_file, options) {
+        Ok(r) => r,
+        Err(e) => panic!("{}", e),
+    };
+    print_warnings(&r);
+    r
+}
+
+fn print_warnings(r: &Root) {
+    for (l, w) in r.warnings() {
+        eprintln!("{}: warning: {}", l, w);
+    }
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let first_file = opt.input;
-    let mut r = Root::new();
-    let mut files: Vec<Vec<DTInfo>> = Vec::new();
-    let mut path: Vec<String> = Vec::new();
-
-    match File::open(&first_file) {
-        Ok(mut f) => {
-            let mut d = String::new();
-            match f.read_to_string(&mut d) {
-                Ok(_o) => match lex(&d) {
-                    Ok(t) => files.push(t),
-                    Err(e) => panic!("Err {:?} when lexxing {}", e, first_file),
-                },
-                Err(e) => panic!("Could not read file {}. Error: {}", first_file, e),
+    let options = dt_load::Options {
+        include_dirs: opt.include_dirs.clone(),
+        // Like cpp, -D NAME defines NAME as 1.
+        defines: opt
+            .defines
+            .iter()
+            .map(|d| match d.split_once('=') {
+                Some((n, v)) => (n.to_string(), v.to_string()),
+                None => (d.to_string(), "1".to_string()),
+            })
+            .collect(),
+        include_once: opt.include_once,
+    };
+    match opt.cmd {
+        Some(Command::Validate { schemas, input }) => {
+            let r = load(&input, &options);
+            let mut loaded = Vec::new();
+            for dir in schemas {
+                match dt_schema::load_dir(Path::new(&dir)) {
+                    Ok(mut s) => loaded.append(&mut s),
+                    Err(e) => panic!("Error loading schemas from {}: {}", dir, e),
+                }
+            }
+            let violations = dt_schema::validate(&r, &loaded);
+            dt_schema::print_violations(&violations);
+            if !violations.is_empty() {
+                process::exit(1);
             }
+            return;
         }
-        Err(e) => panic!("could not open {}. Error: {}", first_file, e),
-    }
-
-    while !files.is_empty() {
-        let mut current = files.pop().unwrap();
-        loop {
-            let token = current.remove(0);
-            match token {
-                DTInfo::Include(i) => match File::open(&i) {
-                    Ok(mut f) => {
-                        let mut d = String::new();
-                        match f.read_to_string(&mut d) {
-                            Ok(_o) => match lex(&d) {
-                                Ok(t) => {
-                                    files.push(current);
-                                    files.push(t);
-                                    break;
-                                }
-                                Err(e) => panic!("Err {:?} when lexxing {}", e, i),
-                            },
-                            Err(e) => panic!("Error reading {}: {:?}", i, e),
-                        }
-                    }
-                    Err(e) => panic!("Error opening {}: {:?}", i, e),
-                },
-                DTInfo::Directive(d, t) => {
-                    match d.as_str() {
-                        "delete-node" => {
-                            match t {
-                                Some(mut t) => {
-                                    if t.starts_with("&") {
-                                        let name = t.split_off(1);
-                                        r = match r.delete_from_label(name) {
-                                            Err(e) => panic!("Error while deleting node: {:?}", e),
-                                            Ok(s) => s,
-                                        };
-                                    } else {
-                                        let new = t.split("/").map(|i| i.to_string());
-                                        let mut p = path.clone();
-                                        p.append(&mut new.collect());
-                                        r = match r.delete_node(p) {
-                                            Err(e) => panic!("Error while deleting node: {:?}", e),
-                                            Ok(s) => s,
-                                        };
-                                    }
-                                }
-                                None => panic!("Unknown node to delete"),
-                            };
-                        }
-                        _ => println!("directive: {}", d),
-                    };
-                }
-                DTInfo::Node(label, name) => {
-                    r = match r.add_node(&path, &name) {
-                        Ok(s) => s,
-                        Err(e) => panic!("Error when adding node: {:?}", e),
-                    };
-                    path.push(name);
-                    if let Some(l) = label {
-                        r.add_path(l, &path);
+        Some(Command::Fmt { check, files }) => {
+            let mut failed = false;
+            for file in files {
+                let text = match fs::read_to_string(&file) {
+                    Ok(t) => t,
+                    Err(e) => panic!("Could not read file {}. Error: {}", file, e),
+                };
+                let formatted = match dt_fmt::format(&text) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        eprintln!("error: {}: {:?}", file, e);
+                        failed = true;
+                        continue;
                     }
+                };
+                if formatted == text {
+                    continue;
                 }
-                DTInfo::NodeEnd => {
-                    path.pop();
-                }
-                DTInfo::Property(p, v) => {
-                    r = match r.add_property(&path, (p, v)) {
-                        Ok(s) => s,
-                        Err(e) => panic!("Error when adding property: {:?}", e),
-                    };
-                }
-                DTInfo::Define(n, v) => {
-                    r = match r.add_define(n, v) {
-                        Ok(s) => s,
-                        Err(e) => panic!("Error when adding define: {:?}", e),
-                    };
-                }
-                DTInfo::EOF => {
-                    break;
-                }
-                DTInfo::RefNode(n) => {
-                    match r.get_path(n) {
-                        Ok(p) => path = p,
-                        Err(e) => panic!("{:?}", e),
-                    };
+                if check {
+                    println!("{} is not formatted", file);
+                    failed = true;
+                } else if let Err(e) = fs::write(&file, formatted) {
+                    panic!("Could not write file {}. Error: {}", file, e);
                 }
             }
+            if failed {
+                process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Lint { json, input }) => {
+            let findings = dt_style::lint_files(&input, &options.include_dirs);
+            if json {
+                dt_style::print_json(&findings);
+            } else {
+                dt_style::print_findings(&findings);
+            }
+            if !findings.is_empty() {
+                process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Zephyr {
+            bindings,
+            properties,
+            input,
+        }) => {
+            let r = load(&input, &options);
+            let loaded = match dt_zephyr::load_dirs(&bindings) {
+                Ok(b) => b,
+                Err(e) => panic!("Error loading bindings: {}", e),
+            };
+            if properties {
+                dt_zephyr::print_properties(&dt_zephyr::effective_properties(&r, &loaded));
+            }
+            let violations = dt_zephyr::validate(&r, &loaded);
+            dt_schema::print_violations(&violations);
+            if !violations.is_empty() {
+                process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Overlay { base, modified }) => {
+            let base = load(&base, &options);
+            let modified = load(&modified, &options);
+            print!("{}", dt_overlay::overlay(&base, &modified));
+            return;
+        }
+        Some(Command::Lsp) => {
+            dt_lsp::run();
+            return;
         }
+        None => (),
+    }
+    let input = match &opt.input {
+        Some(i) => i.clone(),
+        None => clap::Error::with_description(
+            "A device tree file is required",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    if opt.watch {
+        dt_watch::watch(&input, &options, |sources, r| {
+            output(&opt, sources, r);
+        });
+    }
+    let sources = load_sources(&input, &options);
+    let r = match dt_load::build_with(&sources, &input, &options) {
+        Ok(r) => r,
+        Err(e) => panic!("{}", e),
+    };
+    if output(&opt, &sources, r) {
+        process::exit(1);
+    }
+}
+
+// Prints the tree or the requested reports; true if a report found errors.
+fn output(opt: &Opt, sources: &[Source], mut r: Root) -> bool {
+    print_warnings(&r);
+    if let Some(d) = &opt.depfile {
+        let target = match &opt.dep_target {
+            Some(t) => t.clone(),
+            None => Path::new(&sources[0].file)
+                .with_extension("dtb")
+                .display()
+                .to_string(),
+        };
+        if let Err(e) = dt_include::write_depfile(d, &target, sources) {
+            panic!("Could not write file {}. Error: {}", d, e);
+        }
+    }
+    if opt.effective {
+        dt_status::prune(&mut r);
     }
 
-    println!("{:#?}", r);
+    if opt.report.is_empty() {
+        match opt.output {
+            OutputFormat::File => println!("{:#?}", r),
+            OutputFormat::DotFile => print!("{}", dt_dot::tree(&r).render()),
+            OutputFormat::Dts => print!("{}", dt_write::dts(&r)),
+            OutputFormat::Json => println!("{:#}", dt_write::json(&r)),
+            OutputFormat::Header => print!("{}", dt_header::header(&r)),
+        }
+    }
+    let mut failed = false;
+    for report in &opt.report {
+        match report {
+            Report::Reg => dt_address::print_reg(&r),
+            Report::MemoryMap => dt_address::print_memory_map(&r),
+            Report::Translate => dt_address::print_translation(&r),
+            Report::Overlaps => dt_overlap::print_collisions(&r),
+            Report::Interrupts => dt_irq::print_interrupts(&r),
+            Report::IrqConflicts => dt_irq::print_conflicts(&r),
+            Report::Clocks => match opt.output {
+                OutputFormat::DotFile => print!("{}", dt_clock::dot(&r).render()),
+                _ => dt_clock::print_tree(&r),
+            },
+            Report::Gpio => dt_gpio::print_gpios(&r),
+            Report::Pinctrl => dt_pinctrl::print_pinctrl(&r),
+            Report::Status => dt_status::print_status(&r),
+            Report::Checks => {
+                let config = dt_check::Config::new(&opt.warning, &opt.error);
+                let findings = dt_check::run(&r, &config);
+                dt_check::print_findings(&findings);
+                failed |= findings.iter().any(|f| f.level == dt_check::Level::Error);
+            }
+            Report::Includes => match opt.output {
+                OutputFormat::DotFile => print!("{}", dt_include::dot(sources).render()),
+                _ => dt_include::print_tree(sources),
+            },
+        }
+    }
+    failed
 }
@@ -1,140 +1,493 @@
 mod dt_node;
+mod node_ref;
 
-use dt_node::DTNode;
+pub use dt_node::DTNode;
+pub use node_ref::NodeRef;
+use crate::dt_value::{parse, parse_num, strings, Cell, Value};
+use crate::dt_status::{effective, Status};
 use std::collections::HashMap;
-use crate::dt_lexer::DTError;
+use crate::dt_lexer::{lex, DTError, DTInfo};
+use std::fmt;
 
 #[derive(Debug)]
 pub enum RootError {
     MissingNode,
+    MissingProperty,
     UnknownLabel,
     BadPath,
+    BadValue,
     Redefine,
     Err,
 }
-#[derive(Debug)]
+
+impl fmt::Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RootError::MissingNode => write!(f, "no such node"),
+            RootError::MissingProperty => write!(f, "no such property"),
+            RootError::UnknownLabel => write!(f, "unknown label"),
+            RootError::BadPath => write!(f, "bad path"),
+            RootError::BadValue => write!(f, "bad property value"),
+            RootError::Redefine => write!(f, "macro already defined"),
+            RootError::Err => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Root {
     defines: HashMap<String, String>,
     labels: HashMap<String, Vec<String>>,
+    memreserve: Vec<(u64, u64, Location)>,
+    duplicate_labels: Vec<(String, Vec<String>, Vec<String>)>,
+    duplicates: Vec<(Vec<String>, Option<String>, Location)>,
+    warnings: Vec<(Location, String)>,
     the_root: Option<DTNode>,
 }
 
+impl Default for Root {
+    fn default() -> Self {
+        Root::new()
+    }
+}
+
 impl Root {
     pub fn new() -> Self {
         Root {
             defines: HashMap::new(),
             labels: HashMap::new(),
+            memreserve: Vec::new(),
+            duplicate_labels: Vec::new(),
+            duplicates: Vec::new(),
+            warnings: Vec::new(),
             the_root: None,
         }
     }
 
-    pub fn add_define(mut self, d: String, v: String) -> Result<Self, RootError> {
+    // Every edit either succeeds or leaves the tree as it was.
+    pub fn add_define(&mut self, d: String, v: String) -> Result<(), RootError> {
         if self.defines.contains_key(&d) {
-            Err(RootError::Redefine)
-        } else {
-            self.defines.insert(d, v);
-            Ok(self)
+            return Err(RootError::Redefine);
         }
+        self.defines.insert(d, v);
+        Ok(())
     }
 
-    pub fn add_node<P: ToString>(mut self, path: &Vec<P>, name: &P) -> Result<Self, RootError> {
+    // Opening a node that already exists, `/` included, adds to it.
+    pub fn add_node<P: ToString>(&mut self, path: &[P], name: &P) -> Result<(), RootError> {
+        let name = name.to_string();
         match self.the_root.as_mut() {
+            Some(r) if path.is_empty() => {
+                if r.name != name {
+                    return Err(RootError::BadPath);
+                }
+            }
             Some(r) => {
                 let n = find_node(r, path)?;
-                n.add_child(DTNode::new(name.to_string()));
-            }
-            None => {
-                if path.len() > 0 {
-                    return Err(RootError::Err);
-                } else {
-                    self.the_root = Some(DTNode::new(name.to_string()));
+                if n.child(&name).is_none() {
+                    n.add_child(DTNode::new(name));
                 }
             }
+            None if path.is_empty() => self.the_root = Some(DTNode::new(name)),
+            None => return Err(RootError::MissingNode),
         };
-        Ok(self)
+        Ok(())
     }
 
     pub fn add_property<P: ToString>(
-        mut self,
-        path: &Vec<P>,
+        &mut self,
+        path: &[P],
         props: (String, Option<String>),
-    ) -> Result<Self, RootError> {
+    ) -> Result<(), RootError> {
         match self.the_root.as_mut() {
-            Some(r) => {
-                let n = find_node(r, &path)?;
-                n.add_properties(props);
-            }
-            None => return Err(RootError::Err),
+            Some(r) => find_node(r, path)?.add_properties(props),
+            None => return Err(RootError::MissingNode),
+        };
+        Ok(())
+    }
+
+    // Sets a property from its DTS spelling, such as `<0x48>` or `"okay"`;
+    // without a value it is a boolean property.
+    pub fn set_property<P: ToString>(
+        &mut self,
+        path: &[P],
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<(), RootError> {
+        let raw = match value {
+            Some(v) => match lex(&format!("{} = {};", name, v)).as_deref() {
+                Ok([(DTInfo::Property(n, v), _), (DTInfo::EOF, _)]) if n == name => v.clone(),
+                _ => return Err(RootError::BadValue),
+            },
+            None => None,
         };
-        return Ok(self);
+        self.add_property(path, (name.to_string(), raw))
+    }
+
+    pub fn delete_property<P: ToString>(
+        &mut self,
+        path: &[P],
+        name: &str,
+    ) -> Result<(), RootError> {
+        match self.the_root.as_mut() {
+            Some(r) => find_node(r, path)?.remove_property(name),
+            None => Err(RootError::MissingNode),
+        }
+    }
+
+    // Runs several edits as one: if `edit` fails, all of them are undone.
+    pub fn transaction<T, F: FnOnce(&mut Root) -> Result<T, RootError>>(
+        &mut self,
+        edit: F,
+    ) -> Result<T, RootError> {
+        let saved = self.clone();
+        let result = edit(self);
+        if result.is_err() {
+            *self = saved;
+        }
+        result
     }
 
     pub fn add_path<P: ToString>(&mut self, l: P, p: &Vec<String>) {
-        self.labels.insert(l.to_string(), p.clone());
+        if let Some(old) = self.labels.insert(l.to_string(), p.clone()) {
+            if old != *p {
+                self.duplicate_labels.push((l.to_string(), old, p.clone()));
+            }
+        }
+    }
+
+    pub fn duplicate_labels(&self) -> &Vec<(String, Vec<String>, Vec<String>)> {
+        &self.duplicate_labels
+    }
+
+    // A node or property (when `property` is set) given twice in one block.
+    pub fn add_duplicate(&mut self, path: &[String], property: Option<String>, loc: Location) {
+        self.duplicates.push((path.to_vec(), property, loc));
+    }
+
+    pub fn duplicates(&self) -> &Vec<(Vec<String>, Option<String>, Location)> {
+        &self.duplicates
+    }
+
+    // Something odd but harmless seen while loading the sources.
+    pub fn add_warning(&mut self, loc: Location, message: String) {
+        self.warnings.push((loc, message));
+    }
+
+    pub fn warnings(&self) -> &Vec<(Location, String)> {
+        &self.warnings
+    }
+
+    pub fn add_location(&mut self, path: &[String], property: Option<String>, loc: Location) {
+        if let Some(r) = self.the_root.as_mut() {
+            if let Ok(n) = find_node(r, path) {
+                n.add_location(property, loc);
+            }
+        }
+    }
+
+    pub fn add_comment(&mut self, path: &[String], property: Option<String>, comment: String) {
+        if let Some(r) = self.the_root.as_mut() {
+            if let Ok(n) = find_node(r, path) {
+                n.add_comment(property, comment);
+            }
+        }
     }
 
-    pub fn get_path<P: ToString>(&mut self, l: P) -> Result<Vec<String>, RootError> {
-        match self.labels.get_mut(&l.to_string()) {
+    pub fn add_end_comment(&mut self, path: &[String], comment: String) {
+        if let Some(r) = self.the_root.as_mut() {
+            if let Ok(n) = find_node(r, path) {
+                n.add_end_comment(comment);
+            }
+        }
+    }
+
+    pub fn add_memreserve(&mut self, address: u64, size: u64, loc: Location) {
+        self.memreserve.push((address, size, loc));
+    }
+
+    pub fn memreserve(&self) -> &Vec<(u64, u64, Location)> {
+        &self.memreserve
+    }
+
+    pub fn get_path<P: ToString>(&self, l: P) -> Result<Vec<String>, RootError> {
+        match self.labels.get(&l.to_string()) {
             Some(p) => Ok(p.clone()),
             None => Err(RootError::UnknownLabel),
         }
     }
 
-    pub fn delete_from_label<P: ToString>(self, l: P) -> Result<Self, RootError> {
-        let mut root = self
-            .the_root
-            .expect("Need to make a node before you delete it");
-        let mut path = match self.labels.get(&l.to_string()) {
-            Some(p) => p.clone(),
-            None => return Err(RootError::UnknownLabel),
+    pub fn get_node<P: ToString>(&self, path: &[P]) -> Option<&DTNode> {
+        let mut n = self.the_root.as_ref()?;
+        if path.is_empty() || n.name != path[0].to_string() {
+            return None;
+        }
+        for p in &path[1..] {
+            n = n.child(&p.to_string())?;
+        }
+        Some(n)
+    }
+
+    pub fn nodes(&self) -> Vec<(Vec<String>, &DTNode)> {
+        let mut found = Vec::new();
+        let mut stack: Vec<(Vec<String>, &DTNode)> = match self.the_root.as_ref() {
+            Some(r) => vec![(vec![r.name.clone()], r)],
+            None => Vec::new(),
         };
-        let node = path.split_off(path.len() - 2)[0].to_string();
-        match find_node(&mut root, &path) {
-            Ok(n) => match n.remove(node) {
-                Ok(_) => Ok(Self {
-                    defines: self.defines,
-                    labels: self.labels,
-                    the_root: Some(root),
-                }),
-                Err(_) => Err(RootError::MissingNode),
-            },
-            Err(e) => Err(e),
+        while let Some((path, node)) = stack.pop() {
+            for c in node.children().into_iter().rev() {
+                let mut p = path.clone();
+                p.push(c.name.clone());
+                stack.push((p, c));
+            }
+            found.push((path, node));
         }
+        found
     }
 
-    pub fn delete_node<P: ToString>(self, path: Vec<P>) -> Result<Self, RootError> {
-        let mut root = self
-            .the_root
-            .expect("Need to make a node before you delete it");
-        let node = match path.last() {
-            Some(n) => n,
-            None => return Err(RootError::BadPath),
+    pub fn is_enabled<P: ToString>(&self, path: &[P]) -> bool {
+        let path: Vec<String> = path.iter().map(|p| p.to_string()).collect();
+        self.get_node(&path).is_some() && effective(self, &path).0 == Status::Okay
+    }
+
+    // A node by its path as written in DTS, e.g. /soc/serial@1000.
+    pub fn find(&self, path: &str) -> Option<&DTNode> {
+        let mut p = vec!["/".to_string()];
+        p.extend(
+            path.split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        );
+        self.get_node(&p)
+    }
+
+    pub fn label_path(&self, label: &str) -> Option<&Vec<String>> {
+        self.labels.get(label)
+    }
+
+    pub fn find_label(&self, label: &str) -> Option<&DTNode> {
+        self.get_node(self.label_path(label)?)
+    }
+
+    // Every cell of a property as a number, macros expanded; None if the
+    // property is missing or holds anything else.
+    pub fn u32s(&self, node: &DTNode, property: &str) -> Option<Vec<u32>> {
+        self.cells(node.property(property)?)
+            .into_iter()
+            .map(|c| match c {
+                Cell::Num(n) => Some(n),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Consecutive cell pairs as 64-bit numbers, high cell first. This suits
+    // properties of 2-cell numbers only; `reg` goes by its parent's
+    // #address-cells and #size-cells, which `decode_reg` reads.
+    pub fn u64s(&self, node: &DTNode, property: &str) -> Option<Vec<u64>> {
+        let cells = self.u32s(node, property)?;
+        if cells.len() % 2 != 0 {
+            return None;
+        }
+        Some(
+            cells
+                .chunks(2)
+                .map(|c| (c[0] as u64) << 32 | c[1] as u64)
+                .collect(),
+        )
+    }
+
+    pub fn strings(&self, node: &DTNode, property: &str) -> Option<Vec<String>> {
+        node.property(property).map(strings)
+    }
+
+    pub fn string(&self, node: &DTNode, property: &str) -> Option<String> {
+        self.strings(node, property)?.into_iter().next()
+    }
+
+    pub fn cell_value(&self, node: &DTNode, property: &str) -> Option<u32> {
+        match self.cells(node.property(property)?).first() {
+            Some(Cell::Num(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn resolve_phandle(&self, cell: &Cell) -> Option<Vec<String>> {
+        match cell {
+            Cell::Ref(l) => self.labels.get(l).cloned(),
+            Cell::Num(n) => self.nodes().into_iter().find_map(|(p, node)| {
+                let phandle = node
+                    .property("phandle")
+                    .or_else(|| node.property("linux,phandle"));
+                match phandle.map(|raw| self.cells(raw)) {
+                    Some(c) if c.first() == Some(&Cell::Num(*n)) => Some(p),
+                    _ => None,
+                }
+            }),
+            Cell::Symbol(_) => None,
+        }
+    }
+
+    pub fn expand(&self, symbol: &str) -> Option<u32> {
+        let mut s = symbol.to_string();
+        for _ in 0..16 {
+            match parse_num(&s) {
+                Some(n) => return Some(n),
+                None => s = self.defines.get(&s)?.trim().to_string(),
+            }
+        }
+        None
+    }
+
+    pub fn cells(&self, raw: &Option<String>) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        if let Some(r) = raw {
+            for v in parse(r) {
+                if let Value::Cells(c) = v {
+                    cells.extend(c.into_iter().map(|c| match c {
+                        Cell::Symbol(s) => match self.expand(&s) {
+                            Some(n) => Cell::Num(n),
+                            None => Cell::Symbol(s),
+                        },
+                        c => c,
+                    }));
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn retain<F: Fn(&[String]) -> bool>(&mut self, keep: F) {
+        let mut gone: Vec<Vec<String>> = Vec::new();
+        for (path, _) in self.nodes() {
+            if path.len() > 1 && !gone.iter().any(|g| path.starts_with(g)) && !keep(&path) {
+                gone.push(path);
+            }
+        }
+        if let Some(r) = self.the_root.as_mut() {
+            for path in &gone {
+                let parent = path[..path.len() - 1].to_vec();
+                if let Ok(n) = find_node(r, &parent) {
+                    n.retain_children(|c| c.name != path[path.len() - 1]);
+                }
+            }
+        }
+        self.labels
+            .retain(|_, p| !gone.iter().any(|g| p.starts_with(g)));
+    }
+
+    pub fn labels(&self) -> Vec<(&String, &Vec<String>)> {
+        let mut found: Vec<(&String, &Vec<String>)> = self.labels.iter().collect();
+        found.sort();
+        found
+    }
+
+    pub fn define(&self, name: &str) -> Option<&String> {
+        self.defines.get(name)
+    }
+
+    pub fn undefine(&mut self, name: &str) -> Option<String> {
+        self.defines.remove(name)
+    }
+
+    pub fn defines(&self) -> Vec<(&String, &String)> {
+        let mut found: Vec<(&String, &String)> = self.defines.iter().collect();
+        found.sort();
+        found
+    }
+
+    pub fn labels_for(&self, path: &[String]) -> Vec<String> {
+        let mut found: Vec<String> = self
+            .labels
+            .iter()
+            .filter(|(_, p)| p.as_slice() == path)
+            .map(|(l, _)| l.clone())
+            .collect();
+        found.sort();
+        found
+    }
+
+    pub fn delete_from_label<P: ToString>(&mut self, l: P) -> Result<(), RootError> {
+        match self.labels.get(&l.to_string()) {
+            Some(p) => {
+                let p = p.clone();
+                self.delete_node(&p)
+            }
+            None => Err(RootError::UnknownLabel),
+        }
+    }
+
+    // Deletes a node and everything below it, with the labels pointing there.
+    pub fn delete_node<P: ToString>(&mut self, path: &[P]) -> Result<(), RootError> {
+        let path: Vec<String> = path.iter().map(|p| p.to_string()).collect();
+        if path.len() < 2 {
+            return Err(RootError::BadPath);
+        }
+        let r = match self.the_root.as_mut() {
+            Some(r) => r,
+            None => return Err(RootError::MissingNode),
         };
-        match find_node(&mut root, &path) {
-            Ok(n) => match n.remove(node.to_string()) {
-                Ok(_) => Ok(Self {
-                    defines: self.defines,
-                    labels: self.labels,
-                    the_root: Some(root),
-                }),
-                Err(_) => Err(RootError::MissingNode),
-            },
-            Err(e) => Err(e),
+        let parent = find_node(r, &path[..path.len() - 1])?;
+        if parent.remove(path[path.len() - 1].clone()).is_err() {
+            return Err(RootError::MissingNode);
         }
+        self.labels.retain(|_, p| !p.starts_with(&path));
+        Ok(())
+    }
+
+    // A handle on the node at `path`, which can move to its parent, children
+    // and siblings.
+    pub fn handle<P: ToString>(&self, path: &[P]) -> Option<NodeRef<'_>> {
+        let path: Vec<String> = path.iter().map(|p| p.to_string()).collect();
+        NodeRef::new(self, path)
+    }
+
+    pub fn root_node(&self) -> Option<NodeRef<'_>> {
+        self.handle(&["/"])
+    }
+}
+
+pub fn path_string(path: &[String]) -> String {
+    if path.len() <= 1 {
+        "/".to_string()
+    } else {
+        path[1..].iter().fold(String::new(), |s, p| s + "/" + p)
     }
 }
 
+// Where a property, or failing that its node, was defined.
+pub fn location(root: &Root, path: &[String], property: Option<&str>) -> Option<Location> {
+    let node = root.get_node(path)?;
+    property
+        .and_then(|p| node.property_location(p))
+        .or_else(|| node.locations().first())
+        .cloned()
+}
+
 fn find_node<'a, P: ToString>(
     root: &'a mut DTNode,
-    path: &Vec<P>,
+    path: &[P],
 ) -> Result<&'a mut DTNode, RootError> {
     let mut n = root;
 
-    if n.name == path[0].to_string() {
+    if !path.is_empty() && n.name == path[0].to_string() {
         for p in &path[1..] {
             match n.get_child(p.to_string()) {
                 Some(o) => n = o,
-                None => return Err(RootError::MissingNode)
+                None => return Err(RootError::MissingNode),
             }
         }
         return Ok(n);
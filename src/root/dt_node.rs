@@ -1,3 +1,4 @@
+use super::{Location, RootError};
 use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
@@ -5,6 +6,11 @@ pub struct DTNode {
     pub name: String,
     children: HashMap<String, DTNode>,
     properties: HashMap<String, Option<String>>,
+    locations: Vec<Location>,
+    property_locations: HashMap<String, Location>,
+    comments: Vec<String>,
+    property_comments: HashMap<String, Vec<String>>,
+    end_comments: Vec<String>,
 }
 
 impl DTNode {
@@ -13,6 +19,11 @@ impl DTNode {
             name: name.into(),
             children: HashMap::new(),
             properties: HashMap::new(),
+            locations: Vec::new(),
+            property_locations: HashMap::new(),
+            comments: Vec::new(),
+            property_comments: HashMap::new(),
+            end_comments: Vec::new(),
         }
     }
 
@@ -22,6 +33,54 @@ impl DTNode {
         self.properties.insert(k, v);
     }
 
+    pub fn add_location(&mut self, property: Option<String>, loc: Location) {
+        match property {
+            Some(p) => {
+                self.property_locations.insert(p, loc);
+            }
+            None => self.locations.push(loc),
+        }
+    }
+
+    pub fn locations(&self) -> &Vec<Location> {
+        &self.locations
+    }
+
+    pub fn property_location(&self, name: &str) -> Option<&Location> {
+        self.property_locations.get(name)
+    }
+
+    pub fn add_comment(&mut self, property: Option<String>, comment: String) {
+        match property {
+            Some(p) => self
+                .property_comments
+                .entry(p)
+                .or_insert_with(Vec::new)
+                .push(comment),
+            None => self.comments.push(comment),
+        }
+    }
+
+    pub fn comments(&self) -> &Vec<String> {
+        &self.comments
+    }
+
+    // Comments left at the end of a block, before its `};`.
+    pub fn add_end_comment(&mut self, comment: String) {
+        self.end_comments.push(comment);
+    }
+
+    pub fn end_comments(&self) -> &Vec<String> {
+        &self.end_comments
+    }
+
+    pub fn property_comments(&self, name: &str) -> &[String] {
+        match self.property_comments.get(name) {
+            Some(c) => c,
+            None => &[],
+        }
+    }
+
     pub fn get_properties(&mut self) -> HashMap<String, DTNode> {
         return self.children.clone();
     }
@@ -34,6 +93,45 @@ impl DTNode {
         self.children.get_mut(&name)
     }
 
+    pub fn child(&self, name: &str) -> Option<&DTNode> {
+        self.children.get(name)
+    }
+
+    pub fn children(&self) -> Vec<&DTNode> {
+        let mut c: Vec<&DTNode> = self.children.values().collect();
+        c.sort_by(|a, b| a.name.cmp(&b.name));
+        c
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Option<String>> {
+        self.properties.get(name)
+    }
+
+    pub fn properties(&self) -> Vec<(&String, &Option<String>)> {
+        let mut p: Vec<(&String, &Option<String>)> = self.properties.iter().collect();
+        p.sort_by(|a, b| a.0.cmp(b.0));
+        p
+    }
+
+    pub fn has_property(&self, name: &str) -> bool {
+        self.properties.contains_key(name)
+    }
+
+    pub fn retain_children<F: FnMut(&DTNode) -> bool>(&mut self, mut keep: F) {
+        self.children.retain(|_, c| keep(c));
+    }
+
+    pub fn remove_property(&mut self, name: &str) -> Result<(), RootError> {
+        match self.properties.remove(name) {
+            Some(_) => {
+                self.property_locations.remove(name);
+                self.property_comments.remove(name);
+                Ok(())
+            }
+            None => Err(RootError::MissingProperty),
+        }
+    }
+
     pub fn remove(&mut self, name: String) -> Result<(), ()> {
         match self.children.remove_entry(&name) {
             Some(_c) => Ok(()),
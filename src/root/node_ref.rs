@@ -0,0 +1,107 @@
+use super::{path_string, DTNode, Root};
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Deref;
+
+// A node together with its place in the tree, so that it can move to its
+// parent and siblings. It derefs to the DTNode.
+#[derive(Clone)]
+pub struct NodeRef<'a> {
+    root: &'a Root,
+    path: Vec<String>,
+    node: &'a DTNode,
+}
+
+impl<'a> NodeRef<'a> {
+    pub(super) fn new(root: &'a Root, path: Vec<String>) -> Option<Self> {
+        let node = root.get_node(&path)?;
+        Some(NodeRef { root, path, node })
+    }
+
+    pub fn root(&self) -> &'a Root {
+        self.root
+    }
+
+    pub fn node(&self) -> &'a DTNode {
+        self.node
+    }
+
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.root.labels_for(&self.path)
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.path.len() < 2 {
+            return None;
+        }
+        NodeRef::new(self.root, self.path[..self.path.len() - 1].to_vec())
+    }
+
+    pub fn child(&self, name: &str) -> Option<Self> {
+        let mut path = self.path.clone();
+        path.push(name.to_string());
+        NodeRef::new(self.root, path)
+    }
+
+    // In name order, like DTNode::children.
+    pub fn children(&self) -> Vec<Self> {
+        self.node
+            .children()
+            .into_iter()
+            .filter_map(|c| self.child(&c.name))
+            .collect()
+    }
+
+    // The parent's other children.
+    pub fn siblings(&self) -> Vec<Self> {
+        match self.parent() {
+            Some(p) => p
+                .children()
+                .into_iter()
+                .filter(|c| c.node.name != self.node.name)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn sibling(&self, offset: isize) -> Option<Self> {
+        let all = self.parent()?.children();
+        let at = all.iter().position(|c| c.node.name == self.node.name)? as isize;
+        all.into_iter().nth((at + offset).try_into().ok()?)
+    }
+
+    pub fn next_sibling(&self) -> Option<Self> {
+        self.sibling(1)
+    }
+
+    pub fn prev_sibling(&self) -> Option<Self> {
+        self.sibling(-1)
+    }
+
+    // This node and every node below it, parents before children.
+    pub fn descendants(&self) -> Vec<Self> {
+        let mut found = vec![self.clone()];
+        for c in self.children() {
+            found.append(&mut c.descendants());
+        }
+        found
+    }
+}
+
+impl<'a> Deref for NodeRef<'a> {
+    type Target = DTNode;
+
+    fn deref(&self) -> &DTNode {
+        self.node
+    }
+}
+
+impl<'a> fmt::Debug for NodeRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NodeRef({})", path_string(&self.path))
+    }
+}